@@ -0,0 +1,794 @@
+//! Assembler for the mnemonic syntax printed by the disassembler.
+//!
+//! ```text
+//! ; comments start with a semicolon
+//! SPEED equ 2             ; constants, also written `SPEED = 2`
+//! include "sprites.asm"   ; resolved by the caller
+//! start:
+//!     LD V0, SPEED * 4
+//!     LD I, LONG sprite   ; F000 NNNN
+//!     JP start
+//! sprite:
+//!     db 0xFF, 0b10000001, $ & 0xFF
+//!     dw 0x1234
+//! ```
+//!
+//! Expressions support `+ - * / % & | ^ << >> ~`, parentheses, labels,
+//! constants and `$` for the address of the current line.
+//!
+//! Octo source is handled separately by [`assemble_octo`].
+
+use crate::instruction::Instruction;
+use crate::memory::PROGRAM_START;
+use std::collections::HashMap;
+use std::fmt;
+
+mod octo;
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+const MAX_EVAL_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_with(source, "<input>", &mut |path| {
+        Err(format!("Cannot include `{}`", path))
+    })
+}
+
+pub fn assemble_octo(source: &str, name: &str) -> Result<Vec<u8>, AsmError> {
+    octo::assemble(source, name)
+}
+
+// `include` asks `resolve` for the contents of the named file
+pub fn assemble_with(
+    source: &str,
+    name: &str,
+    resolve: &mut dyn FnMut(&str) -> Result<String, String>,
+) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    expand(source, name, resolve, 0, &mut lines)?;
+    Assembler::default().run(&lines)
+}
+
+struct SourceLine {
+    file: String,
+    number: usize,
+    tokens: Vec<Token>,
+}
+
+impl SourceLine {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.number,
+            message: message.into(),
+        }
+    }
+}
+
+fn expand(
+    source: &str,
+    name: &str,
+    resolve: &mut dyn FnMut(&str) -> Result<String, String>,
+    depth: usize,
+    out: &mut Vec<SourceLine>,
+) -> Result<(), AsmError> {
+    for (index, text) in source.lines().enumerate() {
+        let error = |message: String| AsmError {
+            file: name.to_string(),
+            line: index + 1,
+            message,
+        };
+        let tokens = tokenize(text).map_err(error)?;
+        if let [Token::Ident(word), rest @ ..] = tokens.as_slice()
+            && word.eq_ignore_ascii_case("include")
+        {
+            let [Token::Str(path)] = rest else {
+                return Err(error("Expected a quoted path after `include`".to_string()));
+            };
+            if depth >= MAX_INCLUDE_DEPTH {
+                return Err(error("Includes are nested too deeply".to_string()));
+            }
+            let included = resolve(path).map_err(error)?;
+            expand(&included, path, resolve, depth + 1, out)?;
+            continue;
+        }
+        out.push(SourceLine {
+            file: name.to_string(),
+            number: index + 1,
+            tokens,
+        });
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Punct(&'static str),
+}
+
+const PUNCTUATION: [&str; 19] = [
+    "<<", ">>", ",", ":", "(", ")", "[", "]", "+", "-", "*", "/", "%", "&", "|", "^", "~", "=", "$",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start();
+        let Some(c) = rest.chars().next() else {
+            break;
+        };
+        if c == ';' {
+            break;
+        }
+        if c == '"' {
+            let end = rest[1..].find('"').ok_or("Unterminated string")?;
+            tokens.push(Token::Str(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(parse_number(&rest[..len])?));
+            rest = &rest[len..];
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '.')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_string()));
+            rest = &rest[len..];
+        } else if let Some(punct) = PUNCTUATION.iter().find(|p| rest.starts_with(**p)) {
+            tokens.push(Token::Punct(punct));
+            rest = &rest[punct.len()..];
+        } else {
+            return Err(format!("Unexpected character `{}`", c));
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Result<i64, String> {
+    let digits = text.replace('_', "");
+    let lower = digits.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        lower.parse()
+    };
+    parsed.map_err(|_| format!("Invalid number `{}`", text))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    Here,
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+// Binary operators from loosest to tightest binding
+const PRECEDENCE: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Punct(op)) = self.peek() {
+            if !PRECEDENCE[level].contains(op) {
+                break;
+            }
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(*n)),
+            Some(Token::Ident(name)) => Ok(Expr::Symbol(name.clone())),
+            Some(Token::Punct("$")) => Ok(Expr::Here),
+            Some(Token::Punct("-")) => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Some(Token::Punct("~")) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Punct("(")) => {
+                let expr = self.expr()?;
+                if !self.eat(")") {
+                    return Err("Expected `)`".to_string());
+                }
+                Ok(expr)
+            }
+            Some(token) => Err(format!("Unexpected {}", describe(token))),
+            None => Err("Expected an expression".to_string()),
+        }
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(name) => format!("`{}`", name),
+        Token::Number(n) => format!("`{}`", n),
+        Token::Str(s) => format!("\"{}\"", s),
+        Token::Punct(p) => format!("`{}`", p),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(Expr),
+    Expr(Expr),
+}
+
+fn register(name: &str) -> Option<u8> {
+    let digit = name.strip_prefix(['v', 'V'])?;
+    if digit.len() == 1 {
+        u8::from_str_radix(digit, 16).ok()
+    } else {
+        None
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    register(name).is_some()
+        || [
+            "I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG", "EQU", "DB", "DW", "INCLUDE",
+        ]
+        .iter()
+        .any(|word| word.eq_ignore_ascii_case(name))
+}
+
+fn operand(tokens: &[Token]) -> Result<Operand, String> {
+    if let [Token::Ident(name)] = tokens {
+        if let Some(x) = register(name) {
+            return Ok(Operand::V(x));
+        }
+        let special = match name.to_ascii_uppercase().as_str() {
+            "I" => Some(Operand::I),
+            "DT" => Some(Operand::Dt),
+            "ST" => Some(Operand::St),
+            "K" => Some(Operand::K),
+            "F" => Some(Operand::F),
+            "HF" => Some(Operand::Hf),
+            "B" => Some(Operand::B),
+            "R" => Some(Operand::R),
+            _ => None,
+        };
+        if let Some(special) = special {
+            return Ok(special);
+        }
+    }
+    if let [Token::Punct("["), Token::Ident(name), Token::Punct("]")] = tokens
+        && name.eq_ignore_ascii_case("I")
+    {
+        return Ok(Operand::IndirectI);
+    }
+    let (long, tokens) = match tokens {
+        [Token::Ident(word), rest @ ..] if word.eq_ignore_ascii_case("long") => (true, rest),
+        _ => (false, tokens),
+    };
+    let mut parser = Parser::new(tokens);
+    let expr = parser.expr()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("Unexpected {}", describe(token)));
+    }
+    Ok(if long {
+        Operand::Long(expr)
+    } else {
+        Operand::Expr(expr)
+    })
+}
+
+// Splits on commas outside of parentheses and brackets
+fn split_operands(tokens: &[Token]) -> Vec<&[Token]> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct("(") | Token::Punct("[") => depth += 1,
+            Token::Punct(")") | Token::Punct("]") => depth -= 1,
+            Token::Punct(",") if depth == 0 => {
+                parts.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&tokens[start..]);
+    parts
+}
+
+enum Statement {
+    Instruction(String, Vec<Operand>),
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction(mnemonic, operands) => {
+                let long = mnemonic.eq_ignore_ascii_case("LD")
+                    && matches!(operands.as_slice(), [Operand::I, Operand::Long(_)]);
+                if long { 4 } else { 2 }
+            }
+            Statement::Bytes(values) => values.len(),
+            Statement::Words(values) => values.len() * 2,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Assembler {
+    labels: HashMap<String, i64>,
+    constants: HashMap<String, Expr>,
+}
+
+impl Assembler {
+    fn run(mut self, lines: &[SourceLine]) -> Result<Vec<u8>, AsmError> {
+        let mut statements = Vec::new();
+        let mut addr = PROGRAM_START;
+        for line in lines {
+            let mut tokens = line.tokens.as_slice();
+            while let [Token::Ident(name), Token::Punct(":"), rest @ ..] = tokens {
+                self.define(name, line)?;
+                self.labels.insert(name.clone(), addr as i64);
+                tokens = rest;
+            }
+            let statement = match tokens {
+                [] => continue,
+                [Token::Ident(name), Token::Ident(equ), rest @ ..]
+                    if equ.eq_ignore_ascii_case("equ") =>
+                {
+                    self.constant(name, rest, line)?;
+                    continue;
+                }
+                [Token::Ident(name), Token::Punct("="), rest @ ..] => {
+                    self.constant(name, rest, line)?;
+                    continue;
+                }
+                [Token::Ident(word), rest @ ..] => {
+                    parse_statement(word, rest).map_err(|e| line.error(e))?
+                }
+                [token, ..] => return Err(line.error(format!("Unexpected {}", describe(token)))),
+            };
+            let size = statement.size();
+            statements.push((addr, statement, line));
+            addr += size;
+        }
+
+        let mut rom = Vec::new();
+        for (addr, statement, line) in statements {
+            let bytes = self
+                .encode(&statement, addr as i64)
+                .map_err(|e| line.error(e))?;
+            rom.extend(bytes);
+        }
+        Ok(rom)
+    }
+
+    fn define(&self, name: &str, line: &SourceLine) -> Result<(), AsmError> {
+        if is_reserved(name) {
+            return Err(line.error(format!("`{}` is a reserved word", name)));
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(line.error(format!("`{}` is already defined", name)));
+        }
+        Ok(())
+    }
+
+    fn constant(
+        &mut self,
+        name: &str,
+        tokens: &[Token],
+        line: &SourceLine,
+    ) -> Result<(), AsmError> {
+        self.define(name, line)?;
+        let mut parser = Parser::new(tokens);
+        let expr = parser.expr().map_err(|e| line.error(e))?;
+        if let Some(token) = parser.peek() {
+            return Err(line.error(format!("Unexpected {}", describe(token))));
+        }
+        self.constants.insert(name.to_string(), expr);
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr, here: i64, depth: usize) -> Result<i64, String> {
+        if depth > MAX_EVAL_DEPTH {
+            return Err("Constant refers to itself".to_string());
+        }
+        Ok(match expr {
+            Expr::Number(n) => *n,
+            Expr::Here => here,
+            Expr::Symbol(name) => match (self.labels.get(name), self.constants.get(name)) {
+                (Some(&addr), _) => addr,
+                (_, Some(expr)) => self.eval(expr, here, depth + 1)?,
+                _ => return Err(format!("Undefined symbol `{}`", name)),
+            },
+            Expr::Negate(inner) => self.eval(inner, here, depth)?.wrapping_neg(),
+            Expr::Not(inner) => !self.eval(inner, here, depth)?,
+            Expr::Binary(op, left, right) => {
+                let (a, b) = (
+                    self.eval(left, here, depth)?,
+                    self.eval(right, here, depth)?,
+                );
+                match *op {
+                    "+" => a.wrapping_add(b),
+                    "-" => a.wrapping_sub(b),
+                    "*" => a.wrapping_mul(b),
+                    "/" | "%" if b == 0 => return Err("Division by zero".to_string()),
+                    "/" => a / b,
+                    "%" => a % b,
+                    "&" => a & b,
+                    "|" => a | b,
+                    "^" => a ^ b,
+                    "<<" => a.wrapping_shl(b as u32),
+                    ">>" => a.wrapping_shr(b as u32),
+                    _ => unreachable!("unknown operator {}", op),
+                }
+            }
+        })
+    }
+
+    fn value(&self, expr: &Expr, here: i64, min: i64, max: i64, what: &str) -> Result<u16, String> {
+        let value = self.eval(expr, here, 0)?;
+        if value < min || value > max {
+            return Err(format!("{} out of range: {}", what, value));
+        }
+        Ok(value as u16)
+    }
+
+    fn encode(&self, statement: &Statement, here: i64) -> Result<Vec<u8>, String> {
+        match statement {
+            Statement::Bytes(values) => values
+                .iter()
+                .map(|expr| Ok(self.value(expr, here, -0x80, 0xFF, "Byte")? as u8))
+                .collect(),
+            Statement::Words(values) => {
+                let mut bytes = Vec::new();
+                for expr in values {
+                    let word = self.value(expr, here, -0x8000, 0xFFFF, "Word")?;
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            Statement::Instruction(mnemonic, operands) => {
+                Ok(self.instruction(mnemonic, operands, here)?.to_bytes())
+            }
+        }
+    }
+
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[Operand],
+        here: i64,
+    ) -> Result<Instruction, String> {
+        use Operand::*;
+        let addr = |expr| self.value(expr, here, 0, 0xFFF, "Address");
+        let byte = |expr| Ok::<u8, String>(self.value(expr, here, -0x80, 0xFF, "Byte")? as u8);
+        let nibble = |expr| Ok::<u8, String>(self.value(expr, here, 0, 0xF, "Nibble")? as u8);
+        let upper = mnemonic.to_ascii_uppercase();
+        let instruction = match (upper.as_str(), operands) {
+            ("CLS", []) => Instruction::ClearScreen,
+            ("RET", []) => Instruction::Return,
+            ("SCD", [Expr(n)]) => Instruction::ScrollDown(nibble(n)?),
+            ("SCU", [Expr(n)]) => Instruction::ScrollUp(nibble(n)?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::LowRes,
+            ("HIGH", []) => Instruction::HighRes,
+            ("JP", [Expr(nnn)]) => Instruction::Jump(addr(nnn)?),
+            ("JP", [V(0), Expr(nnn)]) => Instruction::JumpOffset(addr(nnn)?),
+            ("CALL", [Expr(nnn)]) => Instruction::Call(addr(nnn)?),
+            ("SE", [V(x), V(y)]) => Instruction::SkipEqReg { x: *x, y: *y },
+            ("SE", [V(x), Expr(nn)]) => Instruction::SkipEqImm {
+                x: *x,
+                nn: byte(nn)?,
+            },
+            ("SNE", [V(x), V(y)]) => Instruction::SkipNeReg { x: *x, y: *y },
+            ("SNE", [V(x), Expr(nn)]) => Instruction::SkipNeImm {
+                x: *x,
+                nn: byte(nn)?,
+            },
+            ("SAVE", [V(x), V(y)]) => Instruction::SaveRange { x: *x, y: *y },
+            ("LOAD", [V(x), V(y)]) => Instruction::LoadRange { x: *x, y: *y },
+            ("LD", [V(x), V(y)]) => Instruction::LoadReg { x: *x, y: *y },
+            ("LD", [V(x), Expr(nn)]) => Instruction::LoadImm {
+                x: *x,
+                nn: byte(nn)?,
+            },
+            ("LD", [V(x), Dt]) => Instruction::LoadDelay { x: *x },
+            ("LD", [V(x), K]) => Instruction::WaitKey { x: *x },
+            ("LD", [V(x), IndirectI]) => Instruction::LoadRegs { x: *x },
+            ("LD", [V(x), R]) => Instruction::LoadFlags { x: *x },
+            ("LD", [I, Expr(nnn)]) => Instruction::LoadIndex(addr(nnn)?),
+            ("LD", [I, Long(nnnn)]) => {
+                Instruction::LoadLongIndex(self.value(nnnn, here, 0, 0xFFFF, "Address")?)
+            }
+            ("LD", [Dt, V(x)]) => Instruction::SetDelay { x: *x },
+            ("LD", [St, V(x)]) => Instruction::SetSound { x: *x },
+            ("LD", [F, V(x)]) => Instruction::LoadFont { x: *x },
+            ("LD", [Hf, V(x)]) => Instruction::LoadBigFont { x: *x },
+            ("LD", [B, V(x)]) => Instruction::StoreBcd { x: *x },
+            ("LD", [IndirectI, V(x)]) => Instruction::StoreRegs { x: *x },
+            ("LD", [R, V(x)]) => Instruction::StoreFlags { x: *x },
+            ("ADD", [V(x), V(y)]) => Instruction::AddReg { x: *x, y: *y },
+            ("ADD", [V(x), Expr(nn)]) => Instruction::AddImm {
+                x: *x,
+                nn: byte(nn)?,
+            },
+            ("ADD", [I, V(x)]) => Instruction::AddIndex { x: *x },
+            ("OR", [V(x), V(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [V(x), V(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [V(x), V(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [V(x), V(y)]) => Instruction::SubReg { x: *x, y: *y },
+            ("SHR", [V(x)]) => Instruction::ShiftRight { x: *x, y: *x },
+            ("SHR", [V(x), V(y)]) => Instruction::ShiftRight { x: *x, y: *y },
+            ("SUBN", [V(x), V(y)]) => Instruction::SubN { x: *x, y: *y },
+            ("SHL", [V(x)]) => Instruction::ShiftLeft { x: *x, y: *x },
+            ("SHL", [V(x), V(y)]) => Instruction::ShiftLeft { x: *x, y: *y },
+            ("RND", [V(x), Expr(nn)]) => Instruction::Random {
+                x: *x,
+                nn: byte(nn)?,
+            },
+            ("DRW", [V(x), V(y), Expr(n)]) => Instruction::Draw {
+                x: *x,
+                y: *y,
+                n: nibble(n)?,
+            },
+            ("SKP", [V(x)]) => Instruction::SkipKeyPressed { x: *x },
+            ("SKNP", [V(x)]) => Instruction::SkipKeyNotPressed { x: *x },
+            ("PLANE", [Expr(n)]) => Instruction::SelectPlane(nibble(n)?),
+            ("AUDIO", []) => Instruction::LoadAudio,
+            ("PITCH", [V(x)]) => Instruction::SetPitch { x: *x },
+            _ if KNOWN_MNEMONICS.contains(&upper.as_str()) => {
+                return Err(format!("Invalid operands for `{}`", mnemonic));
+            }
+            _ => return Err(format!("Unknown instruction `{}`", mnemonic)),
+        };
+        Ok(instruction)
+    }
+}
+
+const KNOWN_MNEMONICS: [&str; 31] = [
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE",
+    "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW",
+    "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+fn parse_statement(word: &str, rest: &[Token]) -> Result<Statement, String> {
+    let exprs = |rest: &[Token]| -> Result<Vec<Expr>, String> {
+        split_operands(rest)
+            .into_iter()
+            .map(|tokens| match operand(tokens)? {
+                Operand::Expr(expr) => Ok(expr),
+                _ => Err("Expected an expression".to_string()),
+            })
+            .collect()
+    };
+    if word.eq_ignore_ascii_case("db") {
+        Ok(Statement::Bytes(exprs(rest)?))
+    } else if word.eq_ignore_ascii_case("dw") {
+        Ok(Statement::Words(exprs(rest)?))
+    } else {
+        let operands = split_operands(rest)
+            .into_iter()
+            .map(operand)
+            .collect::<Result<_, _>>()?;
+        Ok(Statement::Instruction(word.to_string(), operands))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsmError, assemble, assemble_with};
+    use crate::disasm::disassemble;
+    use crate::instruction::Instruction;
+
+    fn error(line: usize, message: &str) -> AsmError {
+        AsmError {
+            file: "<input>".to_string(),
+            line,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_instructions() {
+        let source = "
+            CLS
+            LD V3, 0x42       ; comment
+            ld va, vb
+            ADD I, V1
+            LD [I], V5
+            LD V5, [I]
+            LD I, LONG 0x1234
+            DRW V0, V1, 5
+            SHR V2
+            JP V0, 0x300
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                0x00, 0xE0, 0x63, 0x42, 0x8A, 0xB0, 0xF1, 0x1E, 0xF5, 0x55, 0xF5, 0x65, 0xF0, 0x00,
+                0x12, 0x34, 0xD0, 0x15, 0x82, 0x26, 0xB3, 0x00,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_labels_constants_and_expressions() {
+        let source = "
+            COUNT equ 3
+            SIZE = COUNT * 2 + 1
+        start:
+            LD V0, SIZE
+            LD I, data
+            JP start
+        data: db 1, (2 + 3) << 1, -1, $ & 0xFF, COUNT % 2, ~0 & 0xF0
+            dw data, 0xBEEF
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                0x60, 0x07, 0xA2, 0x06, 0x12, 0x00, 0x01, 0x0A, 0xFF, 0x06, 0x01, 0xF0, 0x02, 0x06,
+                0xBE, 0xEF,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            assemble("CLS\nFOO V1"),
+            Err(error(2, "Unknown instruction `FOO`"))
+        );
+        assert_eq!(
+            assemble("LD V1"),
+            Err(error(1, "Invalid operands for `LD`"))
+        );
+        assert_eq!(
+            assemble("\n\nJP nowhere"),
+            Err(error(3, "Undefined symbol `nowhere`"))
+        );
+        assert_eq!(
+            assemble("LD V0, 256"),
+            Err(error(1, "Byte out of range: 256"))
+        );
+        assert_eq!(
+            assemble("a: CLS\na: CLS"),
+            Err(error(2, "`a` is already defined"))
+        );
+        assert_eq!(
+            assemble("V1: CLS"),
+            Err(error(1, "`V1` is a reserved word"))
+        );
+        assert_eq!(
+            assemble("X = Y\nY = X\ndb X"),
+            Err(error(3, "Constant refers to itself"))
+        );
+        assert_eq!(assemble("db 1 +"), Err(error(1, "Expected an expression")));
+        assert_eq!(
+            assemble("LD V0, #1"),
+            Err(error(1, "Unexpected character `#`"))
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let mut resolve = |path: &str| match path {
+            "sprite.asm" => Ok("sprite: db 0xFF\nCOLOR = 1".to_string()),
+            "bad.asm" => Ok("\nJP".to_string()),
+            _ => Err(format!("{} not found", path)),
+        };
+        let source = "LD I, sprite\nPLANE COLOR\ninclude \"sprite.asm\"";
+        assert_eq!(
+            assemble_with(source, "main.asm", &mut resolve),
+            Ok(vec![0xA2, 0x04, 0xF1, 0x01, 0xFF])
+        );
+        let error = assemble_with("include \"bad.asm\"", "main.asm", &mut resolve).unwrap_err();
+        assert_eq!(error.to_string(), "bad.asm:2: Invalid operands for `JP`");
+        let error = assemble_with("\ninclude \"x.asm\"", "main.asm", &mut resolve).unwrap_err();
+        assert_eq!(error.to_string(), "main.asm:2: x.asm not found");
+    }
+
+    #[test]
+    fn test_every_instruction_round_trips() {
+        for opcode in 0..=u16::MAX {
+            let Some(instruction) = Instruction::decode_long(opcode, 0xABCD) else {
+                continue;
+            };
+            let source = instruction.to_string();
+            assert_eq!(assemble(&source), Ok(instruction.to_bytes()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_disassembly_round_trips() {
+        let mut rom = vec![
+            0xA2, 0x0C, 0x22, 0x08, 0x30, 0x00, 0x12, 0x06, 0xD0, 0x01, 0x00, 0xEE, 0xFF, 0x81,
+            0xB2, 0x10, 0xF0, 0x00, 0x02, 0x0E, 0x00, 0xFD,
+        ];
+        rom.extend((0..=255).rev());
+        let text = disassemble(&rom).to_text();
+        assert_eq!(assemble(&text), Ok(rom));
+    }
+}
@@ -0,0 +1,950 @@
+//! Octo front end.
+//!
+//! Follows the reference compiler: execution starts with a `jump main` at
+//! 0x200, which is left out when `: main` is the first thing in the program,
+//! names may be used before they are defined as jump, call and `i :=`
+//! targets, and `:calc` expressions are evaluated right to left with no
+//! operator precedence.
+
+use super::AsmError;
+use crate::memory::PROGRAM_START;
+use std::collections::{HashMap, VecDeque};
+
+const MAX_ROM_END: usize = 0x10000;
+const MAX_MACRO_EXPANSIONS: usize = 0x4000;
+
+pub fn assemble(source: &str, name: &str) -> Result<Vec<u8>, AsmError> {
+    let mut compiler = Compiler::new(tokenize(source));
+    compiler.run().map_err(|(line, message)| AsmError {
+        file: name.to_string(),
+        line,
+        message,
+    })?;
+    Ok(compiler.rom)
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        for text in line.split_whitespace() {
+            tokens.push_back(Token {
+                text: text.to_string(),
+                line: index + 1,
+            });
+        }
+    }
+    tokens
+}
+
+type Error = (usize, String);
+
+enum Fixup {
+    // The low 12 bits of the instruction at the address
+    Nnn,
+    // The 16-bit operand of `i := long`
+    Long,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+enum Flow {
+    // `if ... begin` or `else`, waiting to be pointed past the block
+    Branch(usize),
+    // `loop`, with the `while` exits that `again` patches
+    Loop(usize, Vec<usize>),
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    main_jump: bool,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(usize, Fixup, String, usize)>,
+    flow: Vec<(Flow, usize)>,
+    expansions: usize,
+}
+
+impl Compiler {
+    fn new(tokens: VecDeque<Token>) -> Self {
+        Self {
+            tokens,
+            line: 1,
+            rom: Vec::new(),
+            here: PROGRAM_START + 2,
+            main_jump: true,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            flow: Vec::new(),
+            expansions: 0,
+        }
+    }
+
+    fn run(&mut self) -> Result<(), Error> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if let Some(&(_, line)) = self.flow.last() {
+            return Err((line, "This block is never closed".to_string()));
+        }
+        let main = *self.labels.get("main").ok_or((
+            self.line,
+            "This program is missing a 'main' label".to_string(),
+        ))?;
+        if self.main_jump {
+            self.patch(PROGRAM_START, 0x1000 | main as u16, 0xFFFF);
+        }
+        for (addr, fixup, name, line) in std::mem::take(&mut self.fixups) {
+            let target = *self
+                .labels
+                .get(&name)
+                .ok_or((line, format!("Undefined name '{}'", name)))?;
+            match fixup {
+                Fixup::Nnn => {
+                    if target > 0xFFF {
+                        return Err((
+                            line,
+                            format!("'{}' is out of range for a 12-bit address", name),
+                        ));
+                    }
+                    self.patch(addr, target as u16, 0x0FFF);
+                }
+                Fixup::Long => self.patch(addr, target as u16, 0xFFFF),
+            }
+        }
+        Ok(())
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, Error> {
+        Err((self.line, message.into()))
+    }
+
+    fn next(&mut self) -> Result<String, Error> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error("Unexpected end of program"),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), Error> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("Expected '{}', got '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, value: u8) -> Result<(), Error> {
+        if self.here >= MAX_ROM_END {
+            return self.error("Program is too large");
+        }
+        let index = self.here - PROGRAM_START;
+        if self.rom.len() <= index {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = value;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn inst(&mut self, a: u8, b: u8) -> Result<(), Error> {
+        self.byte(a)?;
+        self.byte(b)
+    }
+
+    fn word(&mut self, word: u16) -> Result<(), Error> {
+        let [a, b] = word.to_be_bytes();
+        self.inst(a, b)
+    }
+
+    fn patch(&mut self, addr: usize, value: u16, mask: u16) {
+        let index = addr - PROGRAM_START;
+        if self.rom.len() < index + 2 {
+            self.rom.resize(index + 2, 0);
+        }
+        let old = u16::from_be_bytes([self.rom[index], self.rom[index + 1]]);
+        let new = (old & !mask) | (value & mask);
+        self.rom[index..index + 2].copy_from_slice(&new.to_be_bytes());
+    }
+
+    fn is_register(&self, token: &str) -> bool {
+        register_index(token).is_some() || self.aliases.contains_key(token)
+    }
+
+    fn register(&mut self) -> Result<u8, Error> {
+        let token = self.next()?;
+        match register_index(&token).or_else(|| self.aliases.get(&token).copied()) {
+            Some(x) => Ok(x),
+            None => self.error(format!("Expected a register, got '{}'", token)),
+        }
+    }
+
+    fn peek_register(&self) -> bool {
+        self.peek().is_some_and(|token| self.is_register(token))
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), Error> {
+        if self.is_register(name) || is_keyword(name) || number(name).is_some() {
+            return self.error(format!("The name '{}' is reserved", name));
+        }
+        Ok(())
+    }
+
+    // A number, constant, known label or `{ calc }` expression
+    fn constant_value(&mut self, token: &str) -> Result<Option<f64>, Error> {
+        if token == "{" {
+            return self.calc().map(Some);
+        }
+        Ok(number(token)
+            .map(|n| n as f64)
+            .or_else(|| self.constants.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|&addr| addr as f64)))
+    }
+
+    fn short_value(&mut self) -> Result<u8, Error> {
+        let token = self.next()?;
+        let Some(value) = self.constant_value(&token)? else {
+            return self.error(format!("Undefined name '{}'", token));
+        };
+        let value = value.floor() as i64;
+        if !(-128..=255).contains(&value) {
+            return self.error(format!("Value '{}' does not fit in a byte", token));
+        }
+        Ok(value as u8)
+    }
+
+    fn tiny_value(&mut self) -> Result<u8, Error> {
+        let token = self.next()?;
+        let value = self.constant_value(&token)?.map(|v| v.floor() as i64);
+        match value {
+            Some(value @ 0..=15) => Ok(value as u8),
+            Some(_) => self.error(format!("Value '{}' does not fit in a nibble", token)),
+            None => self.error(format!("Undefined name '{}'", token)),
+        }
+    }
+
+    // Emits an instruction with a 12-bit address, resolving later names at the end
+    fn address_inst(&mut self, opcode: u16) -> Result<(), Error> {
+        let token = self.next()?;
+        let addr = self.here;
+        match self.constant_value(&token)? {
+            Some(value) => {
+                let value = value.floor() as i64;
+                if !(0..=0xFFF).contains(&value) {
+                    return self.error(format!("Value '{}' does not fit in 12 bits", token));
+                }
+                self.word(opcode | value as u16)
+            }
+            None if is_name(&token) => {
+                self.check_name(&token)?;
+                self.fixups.push((addr, Fixup::Nnn, token, self.line));
+                self.word(opcode)
+            }
+            None => self.error(format!("Expected an address, got '{}'", token)),
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), Error> {
+        let token = self.next()?;
+        if let Some(value) = number(&token) {
+            if !(-128..=255).contains(&value) {
+                return self.error(format!("Value '{}' does not fit in a byte", token));
+            }
+            return self.byte(value as u8);
+        }
+        if self.is_register(&token) {
+            let x = register_index(&token).unwrap_or_else(|| self.aliases[&token]);
+            return self.register_op(x);
+        }
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                if name == "main" && self.here == PROGRAM_START + 2 && self.rom.is_empty() {
+                    self.here = PROGRAM_START;
+                    self.main_jump = false;
+                }
+                self.define_label(name, self.here)
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.define_label(name, self.here + 1)
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let token = self.next()?;
+                match self.constant_value(&token)? {
+                    Some(value) => {
+                        self.constants.insert(name, value);
+                        Ok(())
+                    }
+                    None => self.error(format!("Undefined name '{}'", token)),
+                }
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":alias" => {
+                let name = self.next()?;
+                if register_index(&name).is_some() || is_keyword(&name) {
+                    return self.error(format!("The name '{}' is reserved", name));
+                }
+                let x = self.register()?;
+                self.aliases.insert(name, x);
+                Ok(())
+            }
+            ":macro" => self.define_macro(),
+            ":byte" => {
+                let value = self.short_value()?;
+                self.byte(value)
+            }
+            ":org" => {
+                let token = self.next()?;
+                match self.constant_value(&token)?.map(|v| v.floor() as i64) {
+                    Some(addr) if (PROGRAM_START as i64..MAX_ROM_END as i64).contains(&addr) => {
+                        self.here = addr as usize;
+                        Ok(())
+                    }
+                    _ => self.error(format!("Invalid address '{}' for :org", token)),
+                }
+            }
+            ":call" => self.address_inst(0x2000),
+            ":breakpoint" => self.next().map(|_| ()),
+            "return" | ";" => self.inst(0x00, 0xEE),
+            "clear" => self.inst(0x00, 0xE0),
+            "exit" => self.inst(0x00, 0xFD),
+            "lores" => self.inst(0x00, 0xFE),
+            "hires" => self.inst(0x00, 0xFF),
+            "scroll-right" => self.inst(0x00, 0xFB),
+            "scroll-left" => self.inst(0x00, 0xFC),
+            "scroll-down" => {
+                let n = self.tiny_value()?;
+                self.inst(0x00, 0xC0 | n)
+            }
+            "scroll-up" => {
+                let n = self.tiny_value()?;
+                self.inst(0x00, 0xD0 | n)
+            }
+            "audio" => self.inst(0xF0, 0x02),
+            "plane" => {
+                let n = self.tiny_value()?;
+                self.inst(0xF0 | n, 0x01)
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.inst(0xF0 | x, 0x33)
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let n = if token == "save" { 0x2 } else { 0x3 };
+                    return self.inst(0x50 | x, (y << 4) | n);
+                }
+                self.inst(0xF0 | x, if token == "save" { 0x55 } else { 0x65 })
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.inst(0xF0 | x, 0x75)
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.inst(0xF0 | x, 0x85)
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.tiny_value()?;
+                self.inst(0xD0 | x, (y << 4) | n)
+            }
+            "jump" => self.address_inst(0x1000),
+            "jump0" => self.address_inst(0xB000),
+            "native" => self.address_inst(0x0000),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let nn = match token.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.inst(0xF0 | x, nn)
+            }
+            "i" => self.index_op(),
+            "if" => self.conditional_statement(),
+            "else" => {
+                let Some((Flow::Branch(jump), line)) = self.flow.pop() else {
+                    return self.error("This 'else' does not have a matching 'begin'");
+                };
+                let here = self.here;
+                self.flow.push((Flow::Branch(here), line));
+                self.word(0x1000)?;
+                self.patch(jump, 0x1000 | self.here as u16, 0xFFFF);
+                Ok(())
+            }
+            "end" => {
+                let Some((Flow::Branch(jump), _)) = self.flow.pop() else {
+                    return self.error("This 'end' does not have a matching 'begin'");
+                };
+                self.patch(jump, 0x1000 | self.here as u16, 0xFFFF);
+                Ok(())
+            }
+            "loop" => {
+                self.flow
+                    .push((Flow::Loop(self.here, Vec::new()), self.line));
+                Ok(())
+            }
+            "while" => {
+                self.condition(true)?;
+                let here = self.here;
+                match self.flow.iter_mut().rev().find_map(|(flow, _)| match flow {
+                    Flow::Loop(_, exits) => Some(exits),
+                    Flow::Branch(_) => None,
+                }) {
+                    Some(exits) => exits.push(here),
+                    None => return self.error("This 'while' is not within a loop"),
+                }
+                self.word(0x1000)
+            }
+            "again" => {
+                let Some((Flow::Loop(start, exits), _)) = self.flow.pop() else {
+                    return self.error("This 'again' does not have a matching 'loop'");
+                };
+                self.word(0x1000 | start as u16)?;
+                for exit in exits {
+                    self.patch(exit, 0x1000 | self.here as u16, 0xFFFF);
+                }
+                Ok(())
+            }
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token),
+            _ if self.constants.contains_key(&token) => {
+                let value = self.constants[&token].floor() as i64;
+                self.byte(value as u8)
+            }
+            _ if is_name(&token) && !is_keyword(&token) => {
+                let addr = self.here;
+                match self.labels.get(&token) {
+                    Some(&target) => self.word(0x2000 | target as u16),
+                    None => {
+                        self.fixups.push((addr, Fixup::Nnn, token, self.line));
+                        self.word(0x2000)
+                    }
+                }
+            }
+            _ => self.error(format!("Unrecognized token '{}'", token)),
+        }
+    }
+
+    fn define_label(&mut self, name: String, addr: usize) -> Result<(), Error> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return self.error(format!("The name '{}' has already been defined", name));
+        }
+        self.labels.insert(name, addr);
+        Ok(())
+    }
+
+    fn register_op(&mut self, x: u8) -> Result<(), Error> {
+        let op = self.next()?;
+        if op == ":=" {
+            match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    let nn = self.short_value()?;
+                    return self.inst(0xC0 | x, nn);
+                }
+                Some("key") => {
+                    self.next()?;
+                    return self.inst(0xF0 | x, 0x0A);
+                }
+                Some("delay") => {
+                    self.next()?;
+                    return self.inst(0xF0 | x, 0x07);
+                }
+                _ => {}
+            }
+        }
+        if self.peek_register() {
+            let y = self.register()?;
+            let n = match op.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return self.error(format!("Unrecognized operator '{}'", op)),
+            };
+            return self.inst(0x80 | x, (y << 4) | n);
+        }
+        match op.as_str() {
+            ":=" => {
+                let nn = self.short_value()?;
+                self.inst(0x60 | x, nn)
+            }
+            "+=" => {
+                let nn = self.short_value()?;
+                self.inst(0x70 | x, nn)
+            }
+            "-=" => {
+                let nn = self.short_value()?;
+                self.inst(0x70 | x, nn.wrapping_neg())
+            }
+            _ => self.error(format!("Operator '{}' needs a register on the right", op)),
+        }
+    }
+
+    fn index_op(&mut self) -> Result<(), Error> {
+        let op = self.next()?;
+        match op.as_str() {
+            "+=" => {
+                let x = self.register()?;
+                self.inst(0xF0 | x, 0x1E)
+            }
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.inst(0xF0 | x, 0x29)
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.inst(0xF0 | x, 0x30)
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.word(0xF000)?;
+                    let token = self.next()?;
+                    match self.constant_value(&token)? {
+                        Some(value) => {
+                            let value = value.floor() as i64;
+                            if !(0..=0xFFFF).contains(&value) {
+                                return self
+                                    .error(format!("Value '{}' does not fit in 16 bits", token));
+                            }
+                            self.word(value as u16)
+                        }
+                        None if is_name(&token) => {
+                            self.check_name(&token)?;
+                            self.fixups.push((self.here, Fixup::Long, token, self.line));
+                            self.word(0)
+                        }
+                        None => self.error(format!("Expected an address, got '{}'", token)),
+                    }
+                }
+                _ => self.address_inst(0xA000),
+            },
+            _ => self.error(format!("Unrecognized operator 'i {}'", op)),
+        }
+    }
+
+    fn conditional_statement(&mut self) -> Result<(), Error> {
+        // The block form needs the opposite skip, so look ahead for `begin`
+        let end = self
+            .tokens
+            .iter()
+            .position(|token| token.text == "then" || token.text == "begin");
+        let Some(end) = end else {
+            return self.error("Expected 'then' or 'begin' after 'if'");
+        };
+        let begin = self.tokens[end].text == "begin";
+        self.condition(begin)?;
+        if begin {
+            self.expect("begin")?;
+            self.flow.push((Flow::Branch(self.here), self.line));
+            self.word(0x1000)
+        } else {
+            self.expect("then")
+        }
+    }
+
+    // Emits a skip over the next instruction for when the condition is false,
+    // or for when it is true if `negated`
+    fn condition(&mut self, negated: bool) -> Result<(), Error> {
+        let x = self.register()?;
+        let mut op = self.next()?;
+        if negated {
+            op = match op.as_str() {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">" => "<=",
+                "<=" => ">",
+                ">=" => "<",
+                _ => return self.error(format!("Unrecognized comparison '{}'", op)),
+            }
+            .to_string();
+        }
+        match op.as_str() {
+            "key" => self.inst(0xE0 | x, 0xA1),
+            "-key" => self.inst(0xE0 | x, 0x9E),
+            "==" | "!=" => {
+                let equal = op == "==";
+                if self.peek_register() {
+                    let y = self.register()?;
+                    self.inst(if equal { 0x90 } else { 0x50 } | x, y << 4)
+                } else {
+                    let nn = self.short_value()?;
+                    self.inst(if equal { 0x40 } else { 0x30 } | x, nn)
+                }
+            }
+            "<" | ">" | "<=" | ">=" => {
+                // Compare through VF, which the subtraction leaves as "no borrow"
+                if self.peek_register() {
+                    let y = self.register()?;
+                    self.inst(0x8F, y << 4)?;
+                } else {
+                    let nn = self.short_value()?;
+                    self.inst(0x6F, nn)?;
+                }
+                let (n, skip) = match op.as_str() {
+                    ">" => (0x5, 0x3F),
+                    "<" => (0x7, 0x3F),
+                    ">=" => (0x7, 0x4F),
+                    _ => (0x5, 0x4F),
+                };
+                self.inst(0x8F, (x << 4) | n)?;
+                self.inst(skip, 0x01)
+            }
+            _ => self.error(format!("Unrecognized comparison '{}'", op)),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), Error> {
+        let name = self.next()?;
+        self.check_name(&name)?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            params.push(token);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let Some(token) = self.tokens.pop_front() else {
+                return self.error(format!("Macro '{}' is never closed", name));
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), Error> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return self.error("Too many macro expansions");
+        }
+        let count = self.macros[name].params.len();
+        let mut args = HashMap::new();
+        for i in 0..count {
+            let arg = self.next()?;
+            args.insert(self.macros[name].params[i].clone(), arg);
+        }
+        let line = self.line;
+        let expanded: Vec<Token> = self.macros[name]
+            .body
+            .iter()
+            .map(|token| Token {
+                text: args.get(&token.text).unwrap_or(&token.text).clone(),
+                line,
+            })
+            .collect();
+        for token in expanded.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    // Parses up to the closing `}` and evaluates the expression
+    fn calc(&mut self) -> Result<f64, Error> {
+        let value = self.calc_expr()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn calc_expr(&mut self) -> Result<f64, Error> {
+        let left = self.calc_term()?;
+        let Some(op) = self.peek().map(str::to_string) else {
+            return Ok(left);
+        };
+        if !BINARY_OPERATORS.contains(&op.as_str()) {
+            return Ok(left);
+        }
+        self.next()?;
+        let right = self.calc_expr()?;
+        let (a, b) = (left, right);
+        let (ia, ib) = (a as i64, b as i64);
+        Ok(match op.as_str() {
+            "+" => a + b,
+            "-" => a - b,
+            "*" => a * b,
+            "/" if b == 0.0 => return self.error("Division by zero"),
+            "/" => a / b,
+            "%" if b == 0.0 => return self.error("Division by zero"),
+            "%" => a % b,
+            "&" => (ia & ib) as f64,
+            "|" => (ia | ib) as f64,
+            "^" => (ia ^ ib) as f64,
+            "<<" => (ia << (ib & 63)) as f64,
+            ">>" => (ia >> (ib & 63)) as f64,
+            "pow" => a.powf(b),
+            "min" => a.min(b),
+            "max" => a.max(b),
+            "<" => (a < b) as i64 as f64,
+            ">" => (a > b) as i64 as f64,
+            "<=" => (a <= b) as i64 as f64,
+            ">=" => (a >= b) as i64 as f64,
+            "==" => (a == b) as i64 as f64,
+            _ => (a != b) as i64 as f64,
+        })
+    }
+
+    fn calc_term(&mut self) -> Result<f64, Error> {
+        let token = self.next()?;
+        if token == "(" {
+            let value = self.calc_expr()?;
+            self.expect(")")?;
+            return Ok(value);
+        }
+        let unary = |f: fn(f64) -> f64, this: &mut Self| this.calc_term().map(f);
+        match token.as_str() {
+            "-" => unary(|v| -v, self),
+            "~" => unary(|v| !(v as i64) as f64, self),
+            "!" => unary(|v| (v == 0.0) as i64 as f64, self),
+            "floor" => unary(f64::floor, self),
+            "ceil" => unary(f64::ceil, self),
+            "abs" => unary(f64::abs, self),
+            "sqrt" => unary(f64::sqrt, self),
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => match self.constant_value(&token)? {
+                Some(value) => Ok(value),
+                None => self.error(format!("Undefined name '{}'", token)),
+            },
+        }
+    }
+}
+
+const BINARY_OPERATORS: [&str; 19] = [
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", ">", "<=", ">=",
+    "==", "!=",
+];
+
+const KEYWORDS: [&str; 42] = [
+    ":=",
+    "|=",
+    "&=",
+    "^=",
+    "-=",
+    "=-",
+    "+=",
+    ">>=",
+    "<<=",
+    "==",
+    "!=",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "key",
+    "-key",
+    "hex",
+    "bighex",
+    "random",
+    "delay",
+    ":",
+    ":next",
+    ":unpack",
+    ":breakpoint",
+    ":proto",
+    ":alias",
+    ":const",
+    ":org",
+    "{",
+    "}",
+    "long",
+    "then",
+    "begin",
+    "else",
+    "end",
+    "loop",
+    "again",
+    "while",
+    "if",
+    "i",
+    ";",
+];
+
+fn is_keyword(token: &str) -> bool {
+    KEYWORDS.contains(&token)
+}
+
+fn is_name(token: &str) -> bool {
+    !token.is_empty() && !token.starts_with(|c: char| c.is_ascii_digit() || c == '-')
+}
+
+fn register_index(token: &str) -> Option<u8> {
+    let digit = token.strip_prefix(['v', 'V'])?;
+    if digit.len() == 1 {
+        u8::from_str_radix(digit, 16).ok()
+    } else {
+        None
+    }
+}
+
+fn number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+    use crate::disasm::disassemble;
+
+    fn octo(source: &str) -> Result<Vec<u8>, String> {
+        assemble(source, "test.8o").map_err(|e| e.to_string())
+    }
+
+    fn hex(text: &str) -> Vec<u8> {
+        text.lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(str::split_whitespace)
+            .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+            .collect()
+    }
+
+    macro_rules! corpus {
+        ($($name:literal),+) => {
+            [$((
+                $name,
+                include_str!(concat!("../../tests/octo/", $name, ".8o")),
+                include_str!(concat!("../../tests/octo/", $name, ".hex")),
+            )),+]
+        };
+    }
+
+    #[test]
+    fn test_corpus() {
+        for (name, source, expected) in corpus!("hello", "branches", "loops", "macros", "data") {
+            assert_eq!(octo(source), Ok(hex(expected)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_main_jump() {
+        assert_eq!(octo(": main clear"), Ok(vec![0x00, 0xE0]));
+        assert_eq!(
+            octo(": sub return : main sub"),
+            Ok(vec![0x12, 0x04, 0x00, 0xEE, 0x22, 0x02])
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            octo("clear"),
+            Err("test.8o:1: This program is missing a 'main' label".to_string())
+        );
+        assert_eq!(
+            octo(": main\n  jump nowhere"),
+            Err("test.8o:2: Undefined name 'nowhere'".to_string())
+        );
+        assert_eq!(
+            octo(": main\n\nv0 := 300"),
+            Err("test.8o:3: Value '300' does not fit in a byte".to_string())
+        );
+        assert_eq!(
+            octo(": main\ni := long 0x10000"),
+            Err("test.8o:2: Value '0x10000' does not fit in 16 bits".to_string())
+        );
+        assert_eq!(
+            octo(": main\nloop\nclear"),
+            Err("test.8o:2: This block is never closed".to_string())
+        );
+        assert_eq!(
+            octo(": main\nend"),
+            Err("test.8o:2: This 'end' does not have a matching 'begin'".to_string())
+        );
+        assert_eq!(
+            octo(": main\n: main"),
+            Err("test.8o:2: The name 'main' has already been defined".to_string())
+        );
+        assert_eq!(
+            octo(": main\nv0 += @"),
+            Err("test.8o:2: Undefined name '@'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_disassembly_round_trips() {
+        let rom = vec![
+            0xA2, 0x0C, 0x22, 0x08, 0x30, 0x00, 0x12, 0x06, 0xD0, 0x01, 0x00, 0xEE, 0xFF, 0x81,
+            0xB2, 0x10, 0xF0, 0x00, 0x02, 0x0E, 0x00, 0xFD, 0x8A, 0xB7, 0x5A, 0xB2,
+        ];
+        let source = disassemble(&rom).to_octo();
+        assert_eq!(octo(&source), Ok(rom));
+    }
+}
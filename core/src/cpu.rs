@@ -1,21 +1,69 @@
 use crate::{
     display::Display,
-    memory::{Memory, PROGRAM_START},
+    instruction::Instruction,
+    memory::{BIG_FONT_START, Memory, PROGRAM_START},
+    quirks::Quirks,
+    random::RandomSource,
+    savestate::{StateError, StateReader, StateWriter},
+    watch::StopReason,
 };
-use rand::Rng;
+use std::fmt;
+
+// A copy of the registers, with the stack trimmed to its used entries
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub i: usize,
+    pub pc: usize,
+    pub sp: u8,
+    pub stack: Vec<usize>,
+    pub dt: u8,
+    pub st: u8,
+}
 
-macro_rules! combine_nibbles {
-    ($($nibble:expr),+) => {
-        {
-            [$($nibble),+]
-                .iter()
-                .rev()
-                .enumerate()
-                .fold(0u32, |acc, (i, &v)| acc | ((v as u32) << (i * 4)))
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    Waiting,
+    Exited,
+    // Executed, and a watchpoint fired
+    Stopped(StopReason),
+}
+
+// How far an FX0A has got
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyWait {
+    // For a key that was not already held when the wait began
+    Press,
+    // For this key to be released, with the `key_release` quirk
+    Release(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuFault {
+    UnknownOpcode { opcode: u16, pc: usize },
+    StackOverflow { pc: usize },
+    StackUnderflow { pc: usize },
+    MemoryOutOfBounds { addr: usize },
+}
+
+impl fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuFault::UnknownOpcode { opcode, pc } => {
+                write!(f, "Unknown opcode: {:04X} at 0x{:X}", opcode, pc)
+            }
+            CpuFault::StackOverflow { pc } => write!(f, "Stack overflow at 0x{:X}", pc),
+            CpuFault::StackUnderflow { pc } => write!(f, "Stack underflow at 0x{:X}", pc),
+            CpuFault::MemoryOutOfBounds { addr } => {
+                write!(f, "Memory access out of bounds: 0x{:X}", addr)
+            }
         }
-    };
+    }
 }
 
+impl std::error::Error for CpuFault {}
+
 pub struct Cpu {
     v: [u8; 16],
     pc: usize,
@@ -24,10 +72,21 @@ pub struct Cpu {
     i: usize,
     dt: u8,
     st: u8,
+    rpl: [u8; 16],
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
+    quirks: Quirks,
+    vblank: bool,
+    key_wait: Option<KeyWait>,
+    // Keys held when the FX0A began, which do not count until released
+    held_keys: u16,
+    // Side effects of the last step, checked by watchpoints
+    last_write: Option<(usize, usize)>,
+    last_collision: bool,
 }
 
 impl Cpu {
-    pub fn new() -> Self {
+    pub fn new(quirks: Quirks) -> Self {
         Self {
             v: [0; 16],
             pc: PROGRAM_START,
@@ -36,178 +95,413 @@ impl Cpu {
             i: 0,
             dt: 0,
             st: 0,
+            rpl: [0; 16],
+            pattern: None,
+            pitch: 64,
+            quirks,
+            vblank: false,
+            key_wait: None,
+            held_keys: 0,
+            last_write: None,
+            last_collision: false,
         }
     }
 
-    fn split_byte(&self, byte: u8) -> (u8, u8) {
-        (byte >> 4, byte & 0x0F)
+    pub fn save(&self, writer: &mut StateWriter) {
+        writer.put_u8(self.quirks.to_bits());
+        writer.put_bytes(&self.v);
+        writer.put_u32(self.pc as u32);
+        for &addr in self.stack.iter() {
+            writer.put_u32(addr as u32);
+        }
+        writer.put_u8(self.sp);
+        writer.put_u32(self.i as u32);
+        writer.put_u8(self.dt);
+        writer.put_u8(self.st);
+        writer.put_bytes(&self.rpl);
+        writer.put_bool(self.pattern.is_some());
+        writer.put_bytes(&self.pattern.unwrap_or_default());
+        writer.put_u8(self.pitch);
+        writer.put_bool(self.vblank);
+        let (phase, value) = match self.key_wait {
+            None => (0, 0),
+            Some(KeyWait::Press) => (1, self.held_keys),
+            Some(KeyWait::Release(key)) => (2, key as u16),
+        };
+        writer.put_u8(phase);
+        writer.put_u16(value);
     }
 
-    fn skip_if(&mut self, condition: bool) {
-        if condition {
-            self.pc += 2;
+    pub fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        let quirks = Quirks::from_bits(reader.u8()?);
+        let v = reader.array()?;
+        let pc = reader.u32()? as usize;
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = reader.u32()? as usize;
+        }
+        let sp = reader.u8()?;
+        if sp as usize > stack.len() {
+            return Err(StateError::Invalid("stack pointer"));
         }
+        let i = reader.u32()? as usize;
+        let dt = reader.u8()?;
+        let st = reader.u8()?;
+        let rpl = reader.array()?;
+        let has_pattern = reader.bool()?;
+        let pattern = reader.array()?;
+        let pitch = reader.u8()?;
+        let vblank = reader.bool()?;
+        let (key_wait, held_keys) = match (reader.u8()?, reader.u16()?) {
+            (0, _) => (None, 0),
+            (1, held) => (Some(KeyWait::Press), held),
+            (2, key) if key < 16 => (Some(KeyWait::Release(key as u8)), 0),
+            _ => return Err(StateError::Invalid("key wait")),
+        };
+        Ok(Self {
+            v,
+            pc,
+            stack,
+            sp,
+            i,
+            dt,
+            st,
+            rpl,
+            pattern: has_pattern.then_some(pattern),
+            pitch,
+            quirks,
+            vblank,
+            key_wait,
+            held_keys,
+            last_write: None,
+            last_collision: false,
+        })
     }
 
-    fn execute_0x_8(&mut self, b: u8, c: u8, d: u8) {
-        match d {
-            0x0 => self.v[b as usize] = self.v[c as usize],
-            0x1 => self.v[b as usize] |= self.v[c as usize],
-            0x2 => self.v[b as usize] &= self.v[c as usize],
-            0x3 => self.v[b as usize] ^= self.v[c as usize],
-            0x4 => {
-                let (result, carry) = self.v[b as usize].overflowing_add(self.v[c as usize]);
-                self.v[b as usize] = result;
-                self.v[0xF] = if carry { 1 } else { 0 };
-            }
-            0x5 => {
-                let (result, carry) = self.v[b as usize].overflowing_sub(self.v[c as usize]);
-                self.v[b as usize] = result;
-                self.v[0xF] = if carry { 0 } else { 1 };
-            }
-            0x6 => {
-                self.v[0xF] = self.v[b as usize] & 0x1;
-                self.v[b as usize] >>= 1;
-            }
-            0x7 => {
-                let (result, borrow) = self.v[c as usize].overflowing_sub(self.v[b as usize]);
-                self.v[b as usize] = result;
-                self.v[0xF] = if borrow { 0 } else { 1 };
+    // Advances FX0A, returning the key once it has been pressed, or pressed and
+    // released. Keys held when the wait began have to be let go first, so a
+    // held key doesn't satisfy several waits in a row.
+    fn wait_key(&mut self, key: u16) -> Option<u8> {
+        match self.key_wait {
+            None => {
+                self.key_wait = Some(KeyWait::Press);
+                self.held_keys = key;
+                None
             }
-            0xE => {
-                self.v[0xF] = (self.v[b as usize] & 0x80) >> 7;
-                self.v[b as usize] <<= 1;
+            Some(KeyWait::Press) => {
+                self.held_keys &= key;
+                let pressed = key & !self.held_keys;
+                if pressed == 0 {
+                    return None;
+                }
+                let pressed = pressed.trailing_zeros() as u8;
+                if self.quirks.key_release {
+                    self.key_wait = Some(KeyWait::Release(pressed));
+                    return None;
+                }
+                self.key_wait = None;
+                Some(pressed)
             }
-            _ => {
-                eprintln!("Unknown opcode: 8{:X}{:X}{:X} at 0x{:X}", b, c, d, self.pc);
+            Some(KeyWait::Release(pressed)) => {
+                if key & (1 << pressed) != 0 {
+                    return None;
+                }
+                self.key_wait = None;
+                Some(pressed)
             }
         }
     }
 
-    fn execute_0x_f(&mut self, b: usize, c: u8, d: u8, memory: &mut Memory) {
-        match (c, d) {
-            (0x0, 0x7) => self.v[b] = self.dt,
-            (0x1, 0x5) => self.dt = self.v[b],
-            (0x1, 0x8) => self.st = self.v[b],
-            (0x1, 0xE) => self.i += self.v[b] as usize,
-            (0x2, 0x9) => self.i = (self.v[b] as usize) * 5,
-            (0x3, 0x3) => {
-                let value = self.v[b];
-                memory.data[self.i] = value / 100;
-                memory.data[self.i + 1] = (value % 100) / 10;
-                memory.data[self.i + 2] = value % 10;
-            }
-            (0x5, 0x5) => {
-                for idx in 0..=b {
-                    memory.data[self.i + idx] = self.v[idx];
-                }
-            }
-            (0x6, 0x5) => {
-                for idx in 0..=b {
-                    self.v[idx] = memory.data[self.i + idx];
-                }
-            }
-            _ => {
-                eprintln!("Unknown opcode: F{:X}{:X}{:X} at 0x{:X}", b, c, d, self.pc);
-            }
+    fn read(&self, memory: &Memory, addr: usize) -> Result<u8, CpuFault> {
+        memory
+            .data
+            .get(addr)
+            .copied()
+            .ok_or(CpuFault::MemoryOutOfBounds { addr })
+    }
+
+    fn write(&mut self, memory: &mut Memory, addr: usize, value: u8) -> Result<(), CpuFault> {
+        let byte = memory
+            .data
+            .get_mut(addr)
+            .ok_or(CpuFault::MemoryOutOfBounds { addr })?;
+        *byte = value;
+        self.last_write = Some(match self.last_write {
+            Some((first, last)) => (first.min(addr), last.max(addr)),
+            None => (addr, addr),
+        });
+        Ok(())
+    }
+
+    fn read_word(&self, memory: &Memory, addr: usize) -> Result<u16, CpuFault> {
+        let hi = self.read(memory, addr)?;
+        let lo = self.read(memory, addr + 1)?;
+        Ok(u16::from_be_bytes([hi, lo]))
+    }
+
+    // Skips over F000 NNNN as a whole
+    fn skip_if(&mut self, memory: &Memory, condition: bool) {
+        if condition {
+            let next = self.read_word(memory, self.pc + 2);
+            self.pc += if next.is_ok_and(Instruction::is_long) {
+                4
+            } else {
+                2
+            };
+        }
+    }
+
+    // 5XY2/5XY3 walk the registers backwards when X > Y
+    fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+        let (x, y) = (x as usize, y as usize);
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
+    }
+
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift {
+            self.v[x]
+        } else {
+            self.v[y]
         }
     }
 
-    pub fn step(&mut self, memory: &mut Memory, display: &mut Display, key: u16) {
-        let (a, b) = self.split_byte(memory.data[self.pc]);
-        let (c, d) = self.split_byte(memory.data[self.pc + 1]);
-        match (a, b, c, d) {
-            (0x0, 0x0, 0xE, 0x0) => display.clear(),
-            (_, _, 0xE, 0xE) => {
+    pub fn step(
+        &mut self,
+        memory: &mut Memory,
+        display: &mut Display,
+        rng: &mut dyn RandomSource,
+        key: u16,
+    ) -> Result<StepOutcome, CpuFault> {
+        self.last_write = None;
+        self.last_collision = false;
+        let opcode = self.read_word(memory, self.pc)?;
+        let operand = if Instruction::is_long(opcode) {
+            self.read_word(memory, self.pc + 2)?
+        } else {
+            0
+        };
+        let instruction =
+            Instruction::decode_long(opcode, operand).ok_or(CpuFault::UnknownOpcode {
+                opcode,
+                pc: self.pc,
+            })?;
+        self.execute(instruction, memory, display, rng, key)
+    }
+
+    fn execute(
+        &mut self,
+        instruction: Instruction,
+        memory: &mut Memory,
+        display: &mut Display,
+        rng: &mut dyn RandomSource,
+        key: u16,
+    ) -> Result<StepOutcome, CpuFault> {
+        match instruction {
+            Instruction::ScrollDown(n) => display.scroll_down(n as usize),
+            Instruction::ScrollUp(n) => display.scroll_up(n as usize),
+            Instruction::ClearScreen => display.clear(),
+            Instruction::Return => {
+                if self.sp == 0 {
+                    return Err(CpuFault::StackUnderflow { pc: self.pc });
+                }
                 self.sp -= 1;
                 self.pc = self.stack[self.sp as usize];
-                return;
+                return Ok(StepOutcome::Executed);
             }
-            (0x1, _, _, _) => {
-                self.pc = combine_nibbles!(b, c, d) as usize;
-                return;
+            Instruction::ScrollRight => display.scroll_right(4),
+            Instruction::ScrollLeft => display.scroll_left(4),
+            Instruction::Exit => return Ok(StepOutcome::Exited),
+            Instruction::LowRes => display.set_hires(false),
+            Instruction::HighRes => display.set_hires(true),
+            Instruction::Jump(nnn) => {
+                self.pc = nnn as usize;
+                return Ok(StepOutcome::Executed);
             }
-            (0x2, _, _, _) => {
+            Instruction::Call(nnn) => {
+                if self.sp as usize >= self.stack.len() {
+                    return Err(CpuFault::StackOverflow { pc: self.pc });
+                }
                 self.stack[self.sp as usize] = self.pc + 2;
                 self.sp += 1;
-                self.pc = combine_nibbles!(b, c, d) as usize;
-                return;
-            }
-            (0x3, _, _, _) => {
-                let vx = self.v[b as usize];
-                let nn = combine_nibbles!(c, d) as u8;
-                self.skip_if(vx == nn);
-            }
-            (0x4, _, _, _) => {
-                let vx = self.v[b as usize];
-                let nn = combine_nibbles!(c, d) as u8;
-                self.skip_if(vx != nn);
-            }
-            (0x5, _, _, 0x0) => {
-                let vx = self.v[b as usize];
-                let vy = self.v[c as usize];
-                self.skip_if(vx == vy);
-            }
-            (0x6, _, _, _) => {
-                let value = combine_nibbles!(c, d) as u8;
-                self.v[b as usize] = value;
-            }
-            (0x7, _, _, _) => {
-                let value = combine_nibbles!(c, d) as u8;
-                self.v[b as usize] = self.v[b as usize].wrapping_add(value);
-            }
-            (0x8, _, _, _) => self.execute_0x_8(b, c, d),
-            (0x9, _, _, 0x0) => {
-                let vx = self.v[b as usize];
-                let vy = self.v[c as usize];
-                self.skip_if(vx != vy);
-            }
-            (0xA, _, _, _) => {
-                self.i = combine_nibbles!(b, c, d) as usize;
-            }
-            (0xB, _, _, _) => {
-                self.pc = combine_nibbles!(b, c, d) as usize + self.v[0] as usize;
-                return;
-            }
-            (0xC, _, _, _) => {
-                let rand_byte: u8 = rand::rng().random();
-                let nn = combine_nibbles!(c, d) as u8;
-                self.v[b as usize] = rand_byte & nn;
-            }
-            (0xD, _, _, _) => {
-                let x = self.v[b as usize] as usize;
-                let y = self.v[c as usize] as usize;
-                let collision = display.draw(x, y, &memory.data[self.i..self.i + (d as usize)]);
-                self.v[0xF] = if collision { 1 } else { 0 };
+                self.pc = nnn as usize;
+                return Ok(StepOutcome::Executed);
             }
-            (0xE, _, 0x9, 0xE) => {
-                let idx = self.v[b as usize];
-                self.skip_if(key & (1 << idx) != 0);
+            Instruction::SkipEqImm { x, nn } => self.skip_if(memory, self.v[x as usize] == nn),
+            Instruction::SkipNeImm { x, nn } => self.skip_if(memory, self.v[x as usize] != nn),
+            Instruction::SkipEqReg { x, y } => {
+                self.skip_if(memory, self.v[x as usize] == self.v[y as usize])
             }
-            (0xE, _, 0xA, 0x1) => {
-                let idx = self.v[b as usize];
-                self.skip_if(key & (1 << idx) == 0);
+            Instruction::SaveRange { x, y } => {
+                for (offset, idx) in Self::register_range(x, y).enumerate() {
+                    self.write(memory, self.i + offset, self.v[idx])?;
+                }
             }
-            (0xF, _, 0x0, 0xA) => {
-                if key == 0 {
-                    return;
+            Instruction::LoadRange { x, y } => {
+                for (offset, idx) in Self::register_range(x, y).enumerate() {
+                    self.v[idx] = self.read(memory, self.i + offset)?;
                 }
-                for i in 0..=0xF {
-                    if key & (1 << i) != 0 {
-                        self.v[b as usize] = i as u8;
-                        break;
+            }
+            Instruction::LoadImm { x, nn } => self.v[x as usize] = nn,
+            Instruction::AddImm { x, nn } => {
+                self.v[x as usize] = self.v[x as usize].wrapping_add(nn);
+            }
+            Instruction::LoadReg { x, y } => self.v[x as usize] = self.v[y as usize],
+            Instruction::Or { x, y } => {
+                self.v[x as usize] |= self.v[y as usize];
+                self.reset_vf();
+            }
+            Instruction::And { x, y } => {
+                self.v[x as usize] &= self.v[y as usize];
+                self.reset_vf();
+            }
+            Instruction::Xor { x, y } => {
+                self.v[x as usize] ^= self.v[y as usize];
+                self.reset_vf();
+            }
+            Instruction::AddReg { x, y } => {
+                let (result, carry) = self.v[x as usize].overflowing_add(self.v[y as usize]);
+                self.v[x as usize] = result;
+                self.v[0xF] = if carry { 1 } else { 0 };
+            }
+            Instruction::SubReg { x, y } => {
+                let (result, borrow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
+                self.v[x as usize] = result;
+                self.v[0xF] = if borrow { 0 } else { 1 };
+            }
+            Instruction::ShiftRight { x, y } => {
+                let value = self.shift_source(x as usize, y as usize);
+                self.v[x as usize] = value >> 1;
+                self.v[0xF] = value & 0x1;
+            }
+            Instruction::SubN { x, y } => {
+                let (result, borrow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
+                self.v[x as usize] = result;
+                self.v[0xF] = if borrow { 0 } else { 1 };
+            }
+            Instruction::ShiftLeft { x, y } => {
+                let value = self.shift_source(x as usize, y as usize);
+                self.v[x as usize] = value << 1;
+                self.v[0xF] = (value & 0x80) >> 7;
+            }
+            Instruction::SkipNeReg { x, y } => {
+                self.skip_if(memory, self.v[x as usize] != self.v[y as usize])
+            }
+            Instruction::LoadIndex(nnn) => self.i = nnn as usize,
+            Instruction::JumpOffset(nnn) => {
+                let offset = if self.quirks.jump_vx {
+                    self.v[(nnn >> 8) as usize]
+                } else {
+                    self.v[0]
+                };
+                self.pc = nnn as usize + offset as usize;
+                return Ok(StepOutcome::Executed);
+            }
+            Instruction::Random { x, nn } => {
+                self.v[x as usize] = rng.next_u8() & nn;
+            }
+            Instruction::Draw { x, y, n } => {
+                if self.quirks.display_wait {
+                    if !self.vblank {
+                        return Ok(StepOutcome::Waiting);
                     }
+                    self.vblank = false;
+                }
+                let px = self.v[x as usize] as usize;
+                let py = self.v[y as usize] as usize;
+                // DXY0 draws a 16x16 sprite; each selected plane reads its own sprite
+                let len = if n == 0 { 32 } else { n as usize } * display.selected_planes();
+                let end = self.i + len;
+                // No plane selected means an empty sprite, which can end at address 0
+                let last = end.saturating_sub(1);
+                let sprite = memory
+                    .data
+                    .get(self.i..end)
+                    .ok_or(CpuFault::MemoryOutOfBounds { addr: last })?;
+                let collision = if n == 0 {
+                    display.draw_large(px, py, sprite, self.quirks.clipping)
+                } else {
+                    display.draw(px, py, sprite, self.quirks.clipping)
+                };
+                self.v[0xF] = if collision { 1 } else { 0 };
+                self.last_collision = collision;
+            }
+            Instruction::SkipKeyPressed { x } => {
+                let idx = self.v[x as usize] & 0xF;
+                self.skip_if(memory, key & (1 << idx) != 0);
+            }
+            Instruction::SkipKeyNotPressed { x } => {
+                let idx = self.v[x as usize] & 0xF;
+                self.skip_if(memory, key & (1 << idx) == 0);
+            }
+            Instruction::LoadLongIndex(nnnn) => self.i = nnnn as usize,
+            Instruction::SelectPlane(n) => display.select_planes(n),
+            Instruction::LoadAudio => {
+                let mut pattern = [0; 16];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read(memory, self.i + offset)?;
+                }
+                self.pattern = Some(pattern);
+            }
+            Instruction::LoadDelay { x } => self.v[x as usize] = self.dt,
+            Instruction::WaitKey { x } => match self.wait_key(key) {
+                Some(pressed) => self.v[x as usize] = pressed,
+                None => return Ok(StepOutcome::Waiting),
+            },
+            Instruction::SetDelay { x } => self.dt = self.v[x as usize],
+            Instruction::SetSound { x } => self.st = self.v[x as usize],
+            Instruction::AddIndex { x } => self.i += self.v[x as usize] as usize,
+            Instruction::LoadFont { x } => self.i = (self.v[x as usize] as usize) * 5,
+            Instruction::LoadBigFont { x } => {
+                self.i = BIG_FONT_START + ((self.v[x as usize] & 0xF) as usize) * 10;
+            }
+            Instruction::SetPitch { x } => self.pitch = self.v[x as usize],
+            Instruction::StoreBcd { x } => {
+                let value = self.v[x as usize];
+                self.write(memory, self.i, value / 100)?;
+                self.write(memory, self.i + 1, (value % 100) / 10)?;
+                self.write(memory, self.i + 2, value % 10)?;
+            }
+            Instruction::StoreRegs { x } => {
+                for idx in 0..=x as usize {
+                    self.write(memory, self.i + idx, self.v[idx])?;
+                }
+                if self.quirks.memory_increment {
+                    self.i += x as usize + 1;
+                }
+            }
+            Instruction::LoadRegs { x } => {
+                for idx in 0..=x as usize {
+                    self.v[idx] = self.read(memory, self.i + idx)?;
+                }
+                if self.quirks.memory_increment {
+                    self.i += x as usize + 1;
                 }
             }
-            (0xF, _, _, _) => self.execute_0x_f(b as usize, c, d, memory),
-            _ => {
-                eprintln!(
-                    "Unknown opcode: {:X}{:X}{:X}{:X} at 0x{:X}",
-                    a, b, c, d, self.pc
-                );
+            Instruction::StoreFlags { x } => {
+                let len = x as usize + 1;
+                self.rpl[..len].copy_from_slice(&self.v[..len]);
+            }
+            Instruction::LoadFlags { x } => {
+                let len = x as usize + 1;
+                self.v[..len].copy_from_slice(&self.rpl[..len]);
             }
         }
-        self.pc += 2;
+        self.pc += instruction.size();
+        Ok(StepOutcome::Executed)
+    }
+
+    pub fn vblank(&mut self) {
+        self.vblank = true;
     }
 
     pub fn dec_delay_timer(&mut self) {
@@ -222,38 +516,150 @@ impl Cpu {
         }
     }
 
+    pub fn registers(&self) -> Registers {
+        Registers {
+            v: self.v,
+            i: self.i,
+            pc: self.pc,
+            sp: self.sp,
+            stack: self.stack[..self.sp as usize].to_vec(),
+            dt: self.dt,
+            st: self.st,
+        }
+    }
+
+    pub fn get_v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn get_pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn get_i(&self) -> usize {
+        self.i
+    }
+
+    pub fn get_sp(&self) -> u8 {
+        self.sp
+    }
+
+    // Only the entries in use, innermost call last
+    pub fn get_stack(&self) -> &[usize] {
+        &self.stack[..self.sp as usize]
+    }
+
+    pub fn get_delay_timer(&self) -> u8 {
+        self.dt
+    }
+
     pub fn get_sound_timer(&self) -> u8 {
         self.st
     }
+
+    pub fn get_rpl(&self) -> &[u8; 16] {
+        &self.rpl
+    }
+
+    pub fn get_quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn set_v(&mut self, x: usize, value: u8) {
+        self.v[x] = value;
+    }
+
+    pub fn set_i(&mut self, i: usize) {
+        self.i = i;
+    }
+
+    // Abandons any FX0A in progress
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+        self.key_wait = None;
+    }
+
+    // Entries above the new pointer keep their old return addresses
+    pub fn set_sp(&mut self, sp: u8) {
+        assert!(
+            sp as usize <= self.stack.len(),
+            "Stack pointer out of range"
+        );
+        self.sp = sp;
+    }
+
+    pub fn set_delay_timer(&mut self, dt: u8) {
+        self.dt = dt;
+    }
+
+    pub fn set_sound_timer(&mut self, st: u8) {
+        self.st = st;
+    }
+
+    // The lowest and highest addresses written by the last step
+    pub fn get_last_write(&self) -> Option<(usize, usize)> {
+        self.last_write
+    }
+
+    pub fn get_last_collision(&self) -> bool {
+        self.last_collision
+    }
+
+    // Set while an FX0A is waiting
+    pub fn get_key_wait(&self) -> Option<KeyWait> {
+        self.key_wait
+    }
+
+    // Set by `vblank` and cleared by the next draw that waited for it
+    pub fn get_vblank(&self) -> bool {
+        self.vblank
+    }
+
+    pub fn get_audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.pattern.as_ref()
+    }
+
+    pub fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
 }
 
 impl Default for Cpu {
     fn default() -> Self {
-        Self::new()
+        Self::new(Quirks::default())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::{display::Display, memory::Memory};
-    use super::Cpu;
-
-    fn initialize(program: &[u8]) -> (Cpu, Memory, Display) {
-        let cpu = Cpu::new();
-        let memory = Memory::new(program);
-        let display = Display::new();
-        (cpu, memory, display)
+    use super::super::{
+        asm::assemble,
+        display::Display,
+        memory::{MEMORY_LEN, Memory, XO_MEMORY_LEN},
+        quirks::Quirks,
+        random::SplitMix64,
+    };
+    use super::{Cpu, CpuFault, KeyWait, StepOutcome};
+
+    // Steps with a fixed random source, for tests that don't depend on CXNN
+    fn step(
+        cpu: &mut Cpu,
+        memory: &mut Memory,
+        display: &mut Display,
+        key: u16,
+    ) -> Result<StepOutcome, CpuFault> {
+        cpu.step(memory, display, &mut SplitMix64::new(0), key)
     }
 
-    #[test]
-    fn test_combine_nibbles() {
-        assert_eq!(combine_nibbles!(0xA, 0xB, 0xC), 0xABC);
+    fn initialize(program: &[u8]) -> (Cpu, Memory, Display) {
+        initialize_with_quirks(program, Quirks::default())
     }
 
-    #[test]
-    fn test_split_byte() {
-        let cpu = Cpu::new();
-        assert_eq!(cpu.split_byte(0xAB), (0xA, 0xB));
+    fn initialize_with_quirks(program: &[u8], quirks: Quirks) -> (Cpu, Memory, Display) {
+        let cpu = Cpu::new(quirks);
+        let memory = Memory::new(program, MEMORY_LEN);
+        let display = Display::new();
+        (cpu, memory, display)
     }
 
     #[test]
@@ -262,7 +668,7 @@ mod tests {
         cpu.stack[0] = 0x100;
         cpu.sp = 1;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.sp, 0);
         assert_eq!(cpu.pc, 0x100);
     }
@@ -271,7 +677,7 @@ mod tests {
     fn test_jump() {
         let (mut cpu, mut memory, mut display) = initialize(&[0x12, 0x00]);
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.pc, 0x200);
     }
 
@@ -279,31 +685,39 @@ mod tests {
     fn test_call() {
         let (mut cpu, mut memory, mut display) = initialize(&[0x23, 0x00]);
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.stack[0], 0x202);
         assert_eq!(cpu.sp, 1);
         assert_eq!(cpu.pc, 0x300);
+        let registers = cpu.registers();
+        assert_eq!(registers.stack, vec![0x202]);
+        assert_eq!(registers.pc, 0x300);
+        assert_eq!(cpu.get_stack(), [0x202]);
+        assert_eq!(cpu.get_sp(), 1);
+        assert_eq!(cpu.get_pc(), 0x300);
     }
 
     #[test]
     fn test_skip_if_equal() {
-        let (mut cpu, mut memory, mut display) = initialize(&[0x30, 0x42, 0x00, 0x00, 0x30, 0x41]);
+        let (mut cpu, mut memory, mut display) =
+            initialize(&assemble("SE V0, 0x42\ndb 0, 0\nSE V0, 0x41").unwrap());
         cpu.v[0] = 0x42;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.pc, 0x204);
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.pc, 0x206);
     }
 
     #[test]
     fn test_skip_if_not_equal() {
-        let (mut cpu, mut memory, mut display) = initialize(&[0x40, 0x41, 0x00, 0x00, 0x40, 0x42]);
+        let (mut cpu, mut memory, mut display) =
+            initialize(&assemble("SNE V0, 0x41\ndb 0, 0\nSNE V0, 0x42").unwrap());
         cpu.v[0] = 0x42;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.pc, 0x204);
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.pc, 0x206);
     }
 
@@ -311,7 +725,7 @@ mod tests {
     fn test_load_value() {
         let (mut cpu, mut memory, mut display) = initialize(&[0x62, 0xFF]);
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[2], 0xFF);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -321,7 +735,7 @@ mod tests {
         let (mut cpu, mut memory, mut display) = initialize(&[0x73, 0x0F]);
         cpu.v[3] = 0xF0;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[3], 0xFF);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -331,7 +745,7 @@ mod tests {
         let (mut cpu, mut memory, mut display) = initialize(&[0x85, 0x30]);
         cpu.v[3] = 0xAB;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[5], 0xAB);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -342,7 +756,7 @@ mod tests {
         cpu.v[2] = 0b10101010;
         cpu.v[3] = 0b11001100;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[2], 0b11101110);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -353,7 +767,7 @@ mod tests {
         cpu.v[2] = 0b10101010;
         cpu.v[3] = 0b11001100;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[2], 0b10001000);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -364,7 +778,7 @@ mod tests {
         cpu.v[2] = 0b10101010;
         cpu.v[3] = 0b11001100;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[2], 0b01100110);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -377,11 +791,11 @@ mod tests {
         cpu.v[6] = 1;
         cpu.v[7] = 2;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[4], 44);
         assert_eq!(cpu.v[0xF], 1);
         assert_eq!(cpu.pc, 0x202);
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[6], 3);
         assert_eq!(cpu.v[0xF], 0);
         assert_eq!(cpu.pc, 0x204);
@@ -395,11 +809,11 @@ mod tests {
         cpu.v[6] = 200;
         cpu.v[7] = 150;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[4], 206);
         assert_eq!(cpu.v[0xF], 0);
         assert_eq!(cpu.pc, 0x202);
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[6], 50);
         assert_eq!(cpu.v[0xF], 1);
         assert_eq!(cpu.pc, 0x204);
@@ -410,11 +824,11 @@ mod tests {
         let (mut cpu, mut memory, mut display) = initialize(&[0x82, 0x06, 0x82, 0x06]);
         cpu.v[2] = 0b00000101;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[2], 0b00000010);
         assert_eq!(cpu.v[0xF], 1);
         assert_eq!(cpu.pc, 0x202);
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[2], 0b00000001);
         assert_eq!(cpu.v[0xF], 0);
         assert_eq!(cpu.pc, 0x204);
@@ -428,11 +842,11 @@ mod tests {
         cpu.v[6] = 150;
         cpu.v[7] = 200;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[4], 206); // 256 - 50 = 206
         assert_eq!(cpu.v[0xF], 0);
         assert_eq!(cpu.pc, 0x202);
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[6], 50);
         assert_eq!(cpu.v[0xF], 1);
         assert_eq!(cpu.pc, 0x204);
@@ -443,11 +857,11 @@ mod tests {
         let (mut cpu, mut memory, mut display) = initialize(&[0x82, 0x0E, 0x82, 0x0E]);
         cpu.v[2] = 0b10000001;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[2], 0b00000010);
         assert_eq!(cpu.v[0xF], 1);
         assert_eq!(cpu.pc, 0x202);
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[2], 0b00000100);
         assert_eq!(cpu.v[0xF], 0);
         assert_eq!(cpu.pc, 0x204);
@@ -460,9 +874,9 @@ mod tests {
         cpu.v[1] = 0x43;
         cpu.v[2] = 0x43;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.pc, 0x204);
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.pc, 0x206);
     }
 
@@ -470,7 +884,7 @@ mod tests {
     fn test_set_index() {
         let (mut cpu, mut memory, mut display) = initialize(&[0xA2, 0xF0]);
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.i, 0x2F0);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -480,7 +894,7 @@ mod tests {
         let (mut cpu, mut memory, mut display) = initialize(&[0xB2, 0x00]);
         cpu.v[0] = 0x10;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.pc, 0x210);
     }
 
@@ -488,9 +902,16 @@ mod tests {
     fn test_random_and() {
         let (mut cpu, mut memory, mut display) = initialize(&[0xC3, 0x0F]);
 
-        cpu.step(&mut memory, &mut display, 0);
-        assert_eq!(cpu.v[3] & 0x0F, cpu.v[3]);
+        let mut rng = SplitMix64::new(0);
+        cpu.step(&mut memory, &mut display, &mut rng, 0).unwrap();
+        assert_eq!(cpu.v[3], 0x02);
         assert_eq!(cpu.pc, 0x202);
+
+        // The same seed always produces the same value
+        let (mut cpu, mut memory, mut display) = initialize(&[0xC3, 0xFF]);
+        cpu.step(&mut memory, &mut display, &mut SplitMix64::new(0), 0)
+            .unwrap();
+        assert_eq!(cpu.v[3], 0xE2);
     }
 
     #[test]
@@ -499,13 +920,13 @@ mod tests {
         cpu.v[0] = 1;
         cpu.i = 0x05;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         for (i, data) in [
-            [false, false, true, false],
-            [false, true, true, false],
-            [false, false, true, false],
-            [false, false, true, false],
-            [false, true, true, true],
+            [0, 0, 1, 0],
+            [0, 1, 1, 0],
+            [0, 0, 1, 0],
+            [0, 0, 1, 0],
+            [0, 1, 1, 1],
         ]
         .iter()
         .enumerate()
@@ -515,13 +936,13 @@ mod tests {
         assert_eq!(cpu.v[0xF], 0);
         assert_eq!(cpu.pc, 0x202);
         cpu.i = 0x0A;
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         for (i, data) in [
-            [true, true, false, true],
-            [false, true, true, true],
-            [true, true, false, true],
-            [true, false, true, false],
-            [true, false, false, false],
+            [1, 1, 0, 1],
+            [0, 1, 1, 1],
+            [1, 1, 0, 1],
+            [1, 0, 1, 0],
+            [1, 0, 0, 0],
         ]
         .iter()
         .enumerate()
@@ -538,9 +959,9 @@ mod tests {
         cpu.v[1] = 0x2;
         cpu.v[2] = 0xF;
 
-        cpu.step(&mut memory, &mut display, 0b0100); // Key 2 pressed
+        step(&mut cpu, &mut memory, &mut display, 0b0100).unwrap(); // Key 2 pressed
         assert_eq!(cpu.pc, 0x204);
-        cpu.step(&mut memory, &mut display, 0b0000); // Key F not pressed
+        step(&mut cpu, &mut memory, &mut display, 0b0000).unwrap(); // Key F not pressed
         assert_eq!(cpu.pc, 0x206);
     }
 
@@ -550,9 +971,9 @@ mod tests {
         cpu.v[1] = 0x3;
         cpu.v[2] = 0xE;
 
-        cpu.step(&mut memory, &mut display, 0b0000);
+        step(&mut cpu, &mut memory, &mut display, 0b0000).unwrap();
         assert_eq!(cpu.pc, 0x204);
-        cpu.step(&mut memory, &mut display, 0b0100000000000000);
+        step(&mut cpu, &mut memory, &mut display, 0b0100000000000000).unwrap();
         assert_eq!(cpu.pc, 0x206);
     }
 
@@ -561,7 +982,7 @@ mod tests {
         let (mut cpu, mut memory, mut display) = initialize(&[0xF2, 0x07]);
         cpu.dt = 0x55;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[2], 0x55);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -570,19 +991,54 @@ mod tests {
     fn test_wait_for_key_press() {
         let (mut cpu, mut memory, mut display) = initialize(&[0xF6, 0x0A]);
 
-        cpu.step(&mut memory, &mut display, 0b0000);
+        let outcome = step(&mut cpu, &mut memory, &mut display, 0b0000);
+        assert_eq!(outcome, Ok(StepOutcome::Waiting));
         assert_eq!(cpu.pc, 0x200);
-        cpu.step(&mut memory, &mut display, 0b0100);
+        step(&mut cpu, &mut memory, &mut display, 0b0100).unwrap();
         assert_eq!(cpu.v[6], 0x02);
         assert_eq!(cpu.pc, 0x202);
     }
 
+    #[test]
+    fn test_wait_ignores_held_key() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0xF6, 0x0A]);
+        let mut press = |key| step(&mut cpu, &mut memory, &mut display, key);
+
+        // 5 is still down from before the wait, so only a new press counts
+        assert_eq!(press(0b10_0000), Ok(StepOutcome::Waiting));
+        assert_eq!(press(0b10_0000), Ok(StepOutcome::Waiting));
+        assert_eq!(press(0), Ok(StepOutcome::Waiting));
+        assert_eq!(press(0b10_0000), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.v[6], 5);
+        assert_eq!(cpu.get_key_wait(), None);
+    }
+
+    #[test]
+    fn test_wait_for_key_release_quirk() {
+        let quirks = Quirks {
+            key_release: true,
+            ..Quirks::default()
+        };
+        let (mut cpu, mut memory, mut display) = initialize_with_quirks(&[0xF6, 0x0A], quirks);
+        let mut press = |cpu: &mut Cpu, key| step(cpu, &mut memory, &mut display, key);
+
+        assert_eq!(press(&mut cpu, 0), Ok(StepOutcome::Waiting));
+        assert_eq!(cpu.get_key_wait(), Some(KeyWait::Press));
+        assert_eq!(press(&mut cpu, 0b1000_1000), Ok(StepOutcome::Waiting));
+        assert_eq!(cpu.get_key_wait(), Some(KeyWait::Release(3)));
+        // Releasing another key does not complete it
+        assert_eq!(press(&mut cpu, 0b1000), Ok(StepOutcome::Waiting));
+        assert_eq!(press(&mut cpu, 0), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.v[6], 3);
+        assert_eq!(cpu.pc, 0x202);
+    }
+
     #[test]
     fn test_set_delay_timer() {
         let (mut cpu, mut memory, mut display) = initialize(&[0xF6, 0x15]);
         cpu.v[6] = 0xAA;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.dt, 0xAA);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -592,7 +1048,7 @@ mod tests {
         let (mut cpu, mut memory, mut display) = initialize(&[0xFA, 0x18]);
         cpu.v[0xA] = 0xBB;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.st, 0xBB);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -603,7 +1059,7 @@ mod tests {
         cpu.i = 0x300;
         cpu.v[5] = 0x20;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.i, 0x320);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -613,7 +1069,7 @@ mod tests {
         let (mut cpu, mut memory, mut display) = initialize(&[0xF4, 0x29]);
         cpu.v[4] = 0x5;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.i, 0x19);
         assert_eq!(cpu.pc, 0x202);
     }
@@ -624,7 +1080,7 @@ mod tests {
         cpu.v[3] = 254;
         cpu.i = 0x300;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(memory.data[0x300], 2);
         assert_eq!(memory.data[0x301], 5);
         assert_eq!(memory.data[0x302], 4);
@@ -639,7 +1095,7 @@ mod tests {
         cpu.v[2] = 0x30;
         cpu.i = 0x400;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(memory.data[0x400], 0x10);
         assert_eq!(memory.data[0x401], 0x20);
         assert_eq!(memory.data[0x402], 0x30);
@@ -654,7 +1110,7 @@ mod tests {
         memory.data[0x502] = 0x33;
         cpu.i = 0x500;
 
-        cpu.step(&mut memory, &mut display, 0);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
         assert_eq!(cpu.v[0], 0x11);
         assert_eq!(cpu.v[1], 0x22);
         assert_eq!(cpu.v[2], 0x33);
@@ -663,7 +1119,7 @@ mod tests {
 
     #[test]
     fn test_decrement_delay_timer() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         cpu.dt = 1;
 
         cpu.dec_delay_timer();
@@ -674,7 +1130,7 @@ mod tests {
 
     #[test]
     fn test_decrement_sound_timer() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         cpu.st = 1;
 
         cpu.dec_sound_timer();
@@ -685,9 +1141,319 @@ mod tests {
 
     #[test]
     fn test_get_sound_timer() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         cpu.st = 42;
 
         assert_eq!(cpu.get_sound_timer(), 42);
     }
+
+    #[test]
+    fn test_shift_quirk_uses_vy() {
+        let quirks = Quirks {
+            shift: false,
+            ..Quirks::default()
+        };
+        let (mut cpu, mut memory, mut display) =
+            initialize_with_quirks(&[0x82, 0x36, 0x84, 0x3E], quirks);
+        cpu.v[3] = 0b10000011;
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.v[2], 0b01000001);
+        assert_eq!(cpu.v[0xF], 1);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.v[4], 0b00000110);
+        assert_eq!(cpu.v[0xF], 1);
+    }
+
+    #[test]
+    fn test_shift_sets_vf_after_result() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0x8F, 0x06]);
+        cpu.v[0xF] = 0b00000010;
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn test_memory_increment_quirk() {
+        let quirks = Quirks {
+            memory_increment: true,
+            ..Quirks::default()
+        };
+        let (mut cpu, mut memory, mut display) =
+            initialize_with_quirks(&[0xF2, 0x55, 0xF1, 0x65], quirks);
+        cpu.i = 0x400;
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.i, 0x403);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.i, 0x405);
+    }
+
+    #[test]
+    fn test_jump_vx_quirk() {
+        let quirks = Quirks {
+            jump_vx: true,
+            ..Quirks::default()
+        };
+        let (mut cpu, mut memory, mut display) = initialize_with_quirks(&[0xB3, 0x00], quirks);
+        cpu.v[0] = 0x10;
+        cpu.v[3] = 0x20;
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.pc, 0x320);
+    }
+
+    #[test]
+    fn test_vf_reset_quirk() {
+        let quirks = Quirks {
+            vf_reset: true,
+            ..Quirks::default()
+        };
+        let (mut cpu, mut memory, mut display) =
+            initialize_with_quirks(&[0x82, 0x31, 0x82, 0x32, 0x82, 0x33], quirks);
+
+        for _ in 0..3 {
+            cpu.v[0xF] = 1;
+            step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+            assert_eq!(cpu.v[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn test_display_wait_quirk() {
+        let quirks = Quirks {
+            display_wait: true,
+            ..Quirks::default()
+        };
+        let (mut cpu, mut memory, mut display) =
+            initialize_with_quirks(&[0xD0, 0x15, 0xD0, 0x15], quirks);
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.pc, 0x200);
+        cpu.vblank();
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.pc, 0x202);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0x00, 0x00, 0x80, 0x0F]);
+
+        assert_eq!(
+            step(&mut cpu, &mut memory, &mut display, 0),
+            Err(CpuFault::UnknownOpcode {
+                opcode: 0x0000,
+                pc: 0x200
+            })
+        );
+        cpu.pc = 0x202;
+        assert_eq!(
+            step(&mut cpu, &mut memory, &mut display, 0),
+            Err(CpuFault::UnknownOpcode {
+                opcode: 0x800F,
+                pc: 0x202
+            })
+        );
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0x22, 0x00]);
+
+        for _ in 0..16 {
+            step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        }
+        assert_eq!(
+            step(&mut cpu, &mut memory, &mut display, 0),
+            Err(CpuFault::StackOverflow { pc: 0x200 })
+        );
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0x00, 0xEE]);
+
+        assert_eq!(
+            step(&mut cpu, &mut memory, &mut display, 0),
+            Err(CpuFault::StackUnderflow { pc: 0x200 })
+        );
+    }
+
+    #[test]
+    fn test_memory_out_of_bounds() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0xF2, 0x55, 0xD0, 0x05]);
+        cpu.i = 0xFFE;
+
+        assert_eq!(
+            step(&mut cpu, &mut memory, &mut display, 0),
+            Err(CpuFault::MemoryOutOfBounds { addr: 0x1000 })
+        );
+        cpu.pc = 0x202;
+        assert_eq!(
+            step(&mut cpu, &mut memory, &mut display, 0),
+            Err(CpuFault::MemoryOutOfBounds { addr: 0x1002 })
+        );
+    }
+
+    #[test]
+    fn test_pc_out_of_bounds() {
+        let (mut cpu, mut memory, mut display) = initialize(&[]);
+        cpu.pc = 0xFFF;
+
+        assert_eq!(
+            step(&mut cpu, &mut memory, &mut display, 0),
+            Err(CpuFault::MemoryOutOfBounds { addr: 0x1000 })
+        );
+    }
+
+    #[test]
+    fn test_skip_if_equal_ending_in_ee() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0x3A, 0xEE]);
+        cpu.v[0xA] = 0xEE;
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn test_scroll_and_resolution() {
+        let (mut cpu, mut memory, mut display) =
+            initialize(&[0x00, 0xFF, 0x00, 0xC2, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFE]);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert!(display.is_hires());
+        display.data[0][0] = 1;
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(display.data[2][0], 1);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(display.data[2][4], 1);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(display.data[2][0], 1);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert!(!display.is_hires());
+        assert_eq!(cpu.pc, 0x20A);
+    }
+
+    #[test]
+    fn test_exit() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0x00, 0xFD]);
+
+        let outcome = step(&mut cpu, &mut memory, &mut display, 0);
+        assert_eq!(outcome, Ok(StepOutcome::Exited));
+        assert_eq!(cpu.pc, 0x200);
+    }
+
+    #[test]
+    fn test_draw_large_sprite() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0xD0, 0x10]);
+        cpu.i = 0x300;
+        memory.data[0x300] = 0xFF;
+        memory.data[0x301] = 0xFF;
+        memory.data[0x31F] = 0x01;
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(display.data[0][0..16], [1; 16]);
+        assert_eq!(display.data[15][15], 1);
+        assert_eq!(cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn test_load_big_font_address() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0xF4, 0x30]);
+        cpu.v[4] = 0x5;
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.i, 0x50 + 50);
+        assert_eq!(memory.data[cpu.i], 0xFF);
+    }
+
+    #[test]
+    fn test_store_and_load_flags() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0xF2, 0x75, 0xF2, 0x85]);
+        cpu.v[0] = 1;
+        cpu.v[1] = 2;
+        cpu.v[2] = 3;
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        cpu.v = [0; 16];
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.v[0..4], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_load_long_index() {
+        let (mut cpu, _, mut display) = initialize(&[]);
+        let mut memory = Memory::new(&[0xF0, 0x00, 0xAB, 0xCD], XO_MEMORY_LEN);
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.i, 0xABCD);
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn test_skip_over_long_instruction() {
+        let (mut cpu, mut memory, mut display) =
+            initialize(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x40, 0x00, 0xF0, 0x00]);
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.pc, 0x206);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.pc, 0x208);
+    }
+
+    #[test]
+    fn test_save_and_load_register_range() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0x51, 0x32, 0x53, 0x13]);
+        cpu.v[1] = 0x11;
+        cpu.v[2] = 0x22;
+        cpu.v[3] = 0x33;
+        cpu.i = 0x400;
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(memory.data[0x400..0x403], [0x11, 0x22, 0x33]);
+        assert_eq!(cpu.i, 0x400);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.v[1..4], [0x33, 0x22, 0x11]);
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn test_select_plane_and_draw() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0xF3, 0x01, 0xD0, 0x01]);
+        cpu.i = 0x300;
+        memory.data[0x300] = 0x80;
+        memory.data[0x301] = 0x40;
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(display.plane_mask(), 0b11);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(display.data[0][0..2], [1, 2]);
+    }
+
+    #[test]
+    fn test_draw_without_planes() {
+        // With no plane selected the sprite is empty, even at the bottom of memory
+        let (mut cpu, mut memory, mut display) = initialize(&[0xF0, 0x01, 0xD0, 0x01]);
+        cpu.v[0xF] = 1;
+
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn test_audio_pattern_and_pitch() {
+        let (mut cpu, mut memory, mut display) = initialize(&[0xF0, 0x02, 0xF1, 0x3A]);
+        cpu.i = 0x300;
+        memory.data[0x300] = 0xAA;
+        cpu.v[1] = 100;
+
+        assert_eq!(cpu.get_audio_pattern(), None);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.get_audio_pattern().unwrap()[0], 0xAA);
+        step(&mut cpu, &mut memory, &mut display, 0).unwrap();
+        assert_eq!(cpu.get_pitch(), 100);
+    }
 }
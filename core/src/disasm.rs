@@ -0,0 +1,400 @@
+use crate::instruction::Instruction;
+use crate::memory::PROGRAM_START;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const DATA_ROW: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Line {
+    // `indirect` marks BNNN, whose real target depends on V0
+    Code {
+        addr: usize,
+        instruction: Instruction,
+        indirect: bool,
+    },
+    Data {
+        addr: usize,
+        bytes: Vec<u8>,
+    },
+}
+
+impl Line {
+    pub fn addr(&self) -> usize {
+        match self {
+            Line::Code { addr, .. } | Line::Data { addr, .. } => *addr,
+        }
+    }
+}
+
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    pub labels: BTreeMap<usize, String>,
+}
+
+// Follows control flow from `PROGRAM_START` to separate code from data.
+// Bytes that are never reached are emitted as data.
+pub fn disassemble(rom: &[u8]) -> Disassembly {
+    let end = PROGRAM_START + rom.len();
+    let byte = |addr: usize| rom[addr - PROGRAM_START];
+    let mut instructions: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut is_code = vec![false; rom.len()];
+    let mut targets: BTreeMap<usize, &str> = BTreeMap::new();
+    let mut pending = vec![PROGRAM_START];
+
+    while let Some(mut addr) = pending.pop() {
+        while addr >= PROGRAM_START && addr + 1 < end && !is_code[addr - PROGRAM_START] {
+            let opcode = u16::from_be_bytes([byte(addr), byte(addr + 1)]);
+            let operand = if Instruction::is_long(opcode) && addr + 3 < end {
+                u16::from_be_bytes([byte(addr + 2), byte(addr + 3)])
+            } else if Instruction::is_long(opcode) {
+                break;
+            } else {
+                0
+            };
+            let Some(instruction) = Instruction::decode_long(opcode, operand) else {
+                break;
+            };
+            let size = instruction.size();
+            let span = addr - PROGRAM_START..addr - PROGRAM_START + size;
+            if is_code[span.clone()].iter().any(|&code| code) {
+                break;
+            }
+            is_code[span].fill(true);
+            instructions.insert(addr, instruction);
+
+            let next = addr + size;
+            match instruction {
+                Instruction::Jump(nnn) => {
+                    targets.entry(nnn as usize).or_insert("loc");
+                    pending.push(nnn as usize);
+                    break;
+                }
+                Instruction::JumpOffset(nnn) => {
+                    targets.entry(nnn as usize).or_insert("loc");
+                    pending.push(nnn as usize);
+                    break;
+                }
+                Instruction::Call(nnn) => {
+                    targets.insert(nnn as usize, "sub");
+                    pending.push(nnn as usize);
+                }
+                Instruction::LoadIndex(nnn) => {
+                    targets.entry(nnn as usize).or_insert("data");
+                }
+                Instruction::LoadLongIndex(nnnn) => {
+                    targets.entry(nnnn as usize).or_insert("data");
+                }
+                Instruction::Return | Instruction::Exit => break,
+                Instruction::SkipEqImm { .. }
+                | Instruction::SkipNeImm { .. }
+                | Instruction::SkipEqReg { .. }
+                | Instruction::SkipNeReg { .. }
+                | Instruction::SkipKeyPressed { .. }
+                | Instruction::SkipKeyNotPressed { .. } => {
+                    let long = next + 1 < end
+                        && Instruction::is_long(u16::from_be_bytes([byte(next), byte(next + 1)]));
+                    pending.push(next + if long { 4 } else { 2 });
+                }
+                _ => {}
+            }
+            addr = next;
+        }
+    }
+
+    // Only label addresses that start an instruction or fall in data
+    let labels: BTreeMap<usize, String> = targets
+        .into_iter()
+        .filter(|&(addr, _)| {
+            (PROGRAM_START..end).contains(&addr)
+                && (instructions.contains_key(&addr) || !is_code[addr - PROGRAM_START])
+        })
+        .map(|(addr, kind)| (addr, format!("{}_{:03X}", kind, addr)))
+        .collect();
+
+    let mut lines = Vec::new();
+    let mut addr = PROGRAM_START;
+    while addr < end {
+        if let Some(&instruction) = instructions.get(&addr) {
+            lines.push(Line::Code {
+                addr,
+                instruction,
+                indirect: matches!(instruction, Instruction::JumpOffset(_)),
+            });
+            addr += instruction.size();
+            continue;
+        }
+        let start = addr;
+        addr += 1;
+        while addr < end
+            && addr - start < DATA_ROW
+            && !is_code[addr - PROGRAM_START]
+            && !labels.contains_key(&addr)
+        {
+            addr += 1;
+        }
+        lines.push(Line::Data {
+            addr: start,
+            bytes: rom[start - PROGRAM_START..addr - PROGRAM_START].to_vec(),
+        });
+    }
+    Disassembly { lines, labels }
+}
+
+impl Disassembly {
+    fn target(&self, addr: u16, digits: usize) -> String {
+        match self.labels.get(&(addr as usize)) {
+            Some(label) => label.clone(),
+            None => format!("0x{:0digits$X}", addr, digits = digits),
+        }
+    }
+
+    // Like `Instruction`'s `Display`, with known addresses replaced by labels
+    pub fn mnemonic(&self, instruction: &Instruction) -> String {
+        match *instruction {
+            Instruction::Jump(nnn) => format!("JP {}", self.target(nnn, 3)),
+            Instruction::Call(nnn) => format!("CALL {}", self.target(nnn, 3)),
+            Instruction::LoadIndex(nnn) => format!("LD I, {}", self.target(nnn, 3)),
+            Instruction::JumpOffset(nnn) => format!("JP V0, {}", self.target(nnn, 3)),
+            Instruction::LoadLongIndex(nnnn) => format!("LD I, LONG {}", self.target(nnnn, 4)),
+            _ => instruction.to_string(),
+        }
+    }
+
+    pub fn octo(&self, instruction: &Instruction) -> String {
+        match *instruction {
+            Instruction::ScrollDown(n) => format!("scroll-down {}", n),
+            Instruction::ScrollUp(n) => format!("scroll-up {}", n),
+            Instruction::ClearScreen => "clear".to_string(),
+            Instruction::Return => "return".to_string(),
+            Instruction::ScrollRight => "scroll-right".to_string(),
+            Instruction::ScrollLeft => "scroll-left".to_string(),
+            Instruction::Exit => "exit".to_string(),
+            Instruction::LowRes => "lores".to_string(),
+            Instruction::HighRes => "hires".to_string(),
+            Instruction::Jump(nnn) => format!("jump {}", self.target(nnn, 3)),
+            Instruction::Call(nnn) => match self.labels.get(&(nnn as usize)) {
+                Some(label) => label.clone(),
+                None => format!(":call 0x{:03X}", nnn),
+            },
+            Instruction::SkipEqImm { x, nn } => format!("if v{:x} != 0x{:02X} then", x, nn),
+            Instruction::SkipNeImm { x, nn } => format!("if v{:x} == 0x{:02X} then", x, nn),
+            Instruction::SkipEqReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+            Instruction::SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+            Instruction::LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+            Instruction::LoadImm { x, nn } => format!("v{:x} := 0x{:02X}", x, nn),
+            Instruction::AddImm { x, nn } => format!("v{:x} += 0x{:02X}", x, nn),
+            Instruction::LoadReg { x, y } => format!("v{:x} := v{:x}", x, y),
+            Instruction::Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+            Instruction::And { x, y } => format!("v{:x} &= v{:x}", x, y),
+            Instruction::Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+            Instruction::AddReg { x, y } => format!("v{:x} += v{:x}", x, y),
+            Instruction::SubReg { x, y } => format!("v{:x} -= v{:x}", x, y),
+            Instruction::ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+            Instruction::SubN { x, y } => format!("v{:x} =- v{:x}", x, y),
+            Instruction::ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+            Instruction::SkipNeReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+            Instruction::LoadIndex(nnn) => format!("i := {}", self.target(nnn, 3)),
+            Instruction::JumpOffset(nnn) => format!("jump0 {}", self.target(nnn, 3)),
+            Instruction::Random { x, nn } => format!("v{:x} := random 0x{:02X}", x, nn),
+            Instruction::Draw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+            Instruction::SkipKeyPressed { x } => format!("if v{:x} -key then", x),
+            Instruction::SkipKeyNotPressed { x } => format!("if v{:x} key then", x),
+            Instruction::LoadLongIndex(nnnn) => format!("i := long {}", self.target(nnnn, 4)),
+            Instruction::SelectPlane(n) => format!("plane {}", n),
+            Instruction::LoadAudio => "audio".to_string(),
+            Instruction::LoadDelay { x } => format!("v{:x} := delay", x),
+            Instruction::WaitKey { x } => format!("v{:x} := key", x),
+            Instruction::SetDelay { x } => format!("delay := v{:x}", x),
+            Instruction::SetSound { x } => format!("buzzer := v{:x}", x),
+            Instruction::AddIndex { x } => format!("i += v{:x}", x),
+            Instruction::LoadFont { x } => format!("i := hex v{:x}", x),
+            Instruction::LoadBigFont { x } => format!("i := bighex v{:x}", x),
+            Instruction::SetPitch { x } => format!("pitch := v{:x}", x),
+            Instruction::StoreBcd { x } => format!("bcd v{:x}", x),
+            Instruction::StoreRegs { x } => format!("save v{:x}", x),
+            Instruction::LoadRegs { x } => format!("load v{:x}", x),
+            Instruction::StoreFlags { x } => format!("saveflags v{:x}", x),
+            Instruction::LoadFlags { x } => format!("loadflags v{:x}", x),
+        }
+    }
+
+    // Assembler syntax, with the address and raw bytes of each line in a comment
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for line in self.lines.iter() {
+            if let Some(label) = self.labels.get(&line.addr()) {
+                let _ = writeln!(out, "{}:", label);
+            }
+            let (text, bytes, note) = match line {
+                Line::Code {
+                    instruction,
+                    indirect,
+                    ..
+                } => (
+                    self.mnemonic(instruction),
+                    instruction.to_bytes(),
+                    if *indirect { "  (indirect)" } else { "" },
+                ),
+                Line::Data { bytes, .. } => (db(bytes, ", "), bytes.clone(), ""),
+            };
+            let _ = writeln!(
+                out,
+                "    {:<24}; 0x{:03X}  {}{}",
+                text,
+                line.addr(),
+                hex(&bytes),
+                note
+            );
+        }
+        out
+    }
+
+    pub fn to_octo(&self) -> String {
+        // Octo starts execution at `main`, which it places at 0x200 when it comes first
+        let mut out = String::from(": main\n");
+        for line in self.lines.iter() {
+            if let Some(label) = self.labels.get(&line.addr()) {
+                let _ = writeln!(out, ": {}", label);
+            }
+            match line {
+                Line::Code {
+                    instruction,
+                    indirect,
+                    ..
+                } => {
+                    let note = if *indirect { " # indirect" } else { "" };
+                    let _ = writeln!(out, "\t{}{}", self.octo(instruction), note);
+                }
+                Line::Data { bytes, .. } => {
+                    let _ = writeln!(out, "\t{}", db(bytes, " ").trim_start_matches("db "));
+                }
+            }
+        }
+        out
+    }
+}
+
+fn db(bytes: &[u8], separator: &str) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+    format!("db {}", bytes.join(separator))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    bytes.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Line, disassemble};
+    use crate::instruction::Instruction;
+
+    // 200: LD I, data_20C   202: CALL sub_208   204: SE V0, 0   206: JP 0x206
+    // 208: DRW V0, V0, 1    20A: RET            20C: db 0xFF 0x81
+    fn sample() -> Vec<u8> {
+        vec![
+            0xA2, 0x0C, 0x22, 0x08, 0x30, 0x00, 0x12, 0x06, 0xD0, 0x01, 0x00, 0xEE, 0xFF, 0x81,
+        ]
+    }
+
+    #[test]
+    fn test_code_and_data() {
+        let disassembly = disassemble(&sample());
+        assert_eq!(disassembly.lines.len(), 7);
+        assert_eq!(
+            disassembly.lines[0],
+            Line::Code {
+                addr: 0x200,
+                instruction: Instruction::LoadIndex(0x20C),
+                indirect: false
+            }
+        );
+        assert_eq!(
+            disassembly.lines[6],
+            Line::Data {
+                addr: 0x20C,
+                bytes: vec![0xFF, 0x81]
+            }
+        );
+        let labels: Vec<&str> = disassembly.labels.values().map(String::as_str).collect();
+        assert_eq!(labels, ["loc_206", "sub_208", "data_20C"]);
+    }
+
+    #[test]
+    fn test_skip_follows_both_paths() {
+        // SE V0, 1 skips into a subroutine call that is otherwise unreachable
+        let rom = [0x30, 0x01, 0x00, 0xEE, 0x22, 0x08, 0x00, 0xEE, 0x00, 0xEE];
+        let disassembly = disassemble(&rom);
+        assert!(
+            disassembly
+                .lines
+                .iter()
+                .all(|line| matches!(line, Line::Code { .. }))
+        );
+        assert!(disassembly.labels.contains_key(&0x208));
+    }
+
+    #[test]
+    fn test_unreachable_and_invalid_bytes_are_data() {
+        let rom = [0x12, 0x04, 0xFF, 0xFF, 0x00, 0xEE, 0x01, 0x02];
+        let disassembly = disassemble(&rom);
+        assert_eq!(
+            disassembly.lines[1],
+            Line::Data {
+                addr: 0x202,
+                bytes: vec![0xFF, 0xFF]
+            }
+        );
+        assert_eq!(
+            disassembly.lines[3],
+            Line::Data {
+                addr: 0x206,
+                bytes: vec![0x01, 0x02]
+            }
+        );
+    }
+
+    #[test]
+    fn test_indirect_jump() {
+        let disassembly = disassemble(&[0xB2, 0x02, 0x12, 0x02]);
+        assert!(matches!(
+            disassembly.lines[0],
+            Line::Code { indirect: true, .. }
+        ));
+        assert!(disassembly.to_text().contains("JP V0, loc_202"));
+    }
+
+    #[test]
+    fn test_to_text() {
+        let text = disassemble(&sample()).to_text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "    LD I, data_20C          ; 0x200  A2 0C");
+        assert_eq!(lines[1], "    CALL sub_208            ; 0x202  22 08");
+        assert_eq!(lines[3], "loc_206:");
+        assert_eq!(lines[8], "data_20C:");
+        assert_eq!(lines[9], "    db 0xFF, 0x81           ; 0x20C  FF 81");
+    }
+
+    #[test]
+    fn test_to_octo() {
+        let octo = disassemble(&sample()).to_octo();
+        let lines: Vec<&str> = octo.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                ": main",
+                "\ti := data_20C",
+                "\tsub_208",
+                "\tif v0 != 0x00 then",
+                ": loc_206",
+                "\tjump loc_206",
+                ": sub_208",
+                "\tsprite v0 v0 1",
+                "\treturn",
+                ": data_20C",
+                "\t0xFF 0x81",
+            ]
+        );
+    }
+}
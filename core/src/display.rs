@@ -1,37 +1,188 @@
+use crate::savestate::{StateError, StateReader, StateWriter};
+
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+pub const PLANES: usize = 2;
 
+// Each pixel holds one bit per plane, so its value is an index into a 4-color palette
 pub struct Display {
-    pub data: [[bool; WIDTH]; HEIGHT],
+    pub data: Vec<Vec<u8>>,
+    hires: bool,
+    plane_mask: u8,
 }
 
 impl Display {
     pub fn new() -> Self {
         Self {
-            data: [[false; WIDTH]; HEIGHT],
+            data: vec![vec![0; WIDTH]; HEIGHT],
+            hires: false,
+            plane_mask: 0b01,
+        }
+    }
+
+    pub fn save(&self, writer: &mut StateWriter) {
+        writer.put_bool(self.hires);
+        writer.put_u8(self.plane_mask);
+        for row in self.data.iter() {
+            writer.put_bytes(row);
         }
     }
 
+    pub fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        let mut display = Self::new();
+        display.set_hires(reader.bool()?);
+        let plane_mask = reader.u8()?;
+        if plane_mask > 0b11 {
+            return Err(StateError::Invalid("plane mask"));
+        }
+        display.select_planes(plane_mask);
+        let width = display.width();
+        for row in display.data.iter_mut() {
+            row.copy_from_slice(reader.bytes(width)?);
+            if row.iter().any(|&pixel| pixel > 0b11) {
+                return Err(StateError::Invalid("pixel"));
+            }
+        }
+        Ok(display)
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { WIDTH }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { HEIGHT }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    // Switching resolution clears the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.data = vec![vec![0; self.width()]; self.height()];
+    }
+
+    pub fn plane_mask(&self) -> u8 {
+        self.plane_mask
+    }
+
+    pub fn select_planes(&mut self, mask: u8) {
+        self.plane_mask = mask & 0b11;
+    }
+
+    pub fn selected_planes(&self) -> usize {
+        self.plane_mask.count_ones() as usize
+    }
+
+    fn planes(&self) -> impl Iterator<Item = u8> + use<> {
+        let mask = self.plane_mask;
+        (0..PLANES as u8)
+            .map(|plane| 1 << plane)
+            .filter(move |bit| mask & bit != 0)
+    }
+
     pub fn clear(&mut self) {
-        self.data = [[false; WIDTH]; HEIGHT];
+        let keep = !self.plane_mask;
+        for row in self.data.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= keep;
+            }
+        }
     }
 
-    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+    // `sprite` holds one 8-pixel wide sprite per selected plane, one after another
+    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        let len = sprite.len() / self.selected_planes().max(1);
         let mut collision = false;
-        for (row, byte) in sprite.iter().enumerate() {
-            for bit in 0..8 {
-                if (byte & (0x80 >> bit)) != 0 {
-                    let px = (x + bit) % WIDTH;
-                    let py = (y + row) % HEIGHT;
-                    if self.data[py][px] {
+        for (index, bit) in self.planes().enumerate() {
+            let rows = sprite[index * len..(index + 1) * len]
+                .iter()
+                .map(|&byte| (byte as u16) << 8);
+            collision |= self.draw_rows(x, y, rows, 8, bit, clip);
+        }
+        collision
+    }
+
+    // Draws a 16x16 sprite stored as 32 big-endian bytes per selected plane
+    pub fn draw_large(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        let mut collision = false;
+        for (index, bit) in self.planes().enumerate() {
+            let rows = sprite[index * 32..(index + 1) * 32]
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+            collision |= self.draw_rows(x, y, rows, 16, bit, clip);
+        }
+        collision
+    }
+
+    fn draw_rows(
+        &mut self,
+        x: usize,
+        y: usize,
+        rows: impl Iterator<Item = u16>,
+        sprite_width: usize,
+        plane: u8,
+        clip: bool,
+    ) -> bool {
+        let (width, height) = (self.width(), self.height());
+        let (x, y) = (x % width, y % height);
+        let mut collision = false;
+        for (row, bits) in rows.enumerate() {
+            for bit in 0..sprite_width {
+                if (bits & (0x8000 >> bit)) != 0 {
+                    if clip && (x + bit >= width || y + row >= height) {
+                        continue;
+                    }
+                    let px = (x + bit) % width;
+                    let py = (y + row) % height;
+                    if self.data[py][px] & plane != 0 {
                         collision = true;
                     }
-                    self.data[py][px] ^= true;
+                    self.data[py][px] ^= plane;
                 }
             }
         }
         collision
     }
+
+    // Moves the selected planes by `dx`, `dy` pixels, filling the gap with blank pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let mask = self.plane_mask;
+        let source = self.data.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&sx) && (0..height).contains(&sy) {
+                    source[sy as usize][sx as usize] & mask
+                } else {
+                    0
+                };
+                let pixel = &mut self.data[y as usize][x as usize];
+                *pixel = (*pixel & !mask) | moved;
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
 }
 
 impl Default for Display {
@@ -43,43 +194,139 @@ impl Default for Display {
 #[cfg(test)]
 mod tests {
     use crate::display::Display;
-    use crate::display::{HEIGHT, WIDTH};
+    use crate::display::{HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, WIDTH};
 
     #[test]
     fn test_clear() {
         let mut display = Display::new();
+        display.draw(0, 0, &[0xFF], false);
         display.clear();
-        assert_eq!(display.data, [[false; WIDTH]; HEIGHT]);
+        assert_eq!(display.data, vec![vec![0; WIDTH]; HEIGHT]);
     }
 
     #[test]
     fn test_draw_no_collision() {
         let mut display = Display::new();
         let sprite = [0xF0, 0x10, 0xF0, 0x80, 0xF0]; // '2' from font set
-        let collision = display.draw(0, 0, &sprite);
+        let collision = display.draw(0, 0, &sprite, false);
         assert!(!collision);
-        assert_eq!(display.data[0][0..4], [true, true, true, true]);
-        assert_eq!(display.data[1][0..4], [false, false, false, true]);
-        assert_eq!(display.data[2][0..4], [true, true, true, true]);
-        assert_eq!(display.data[3][0..4], [true, false, false, false]);
-        assert_eq!(display.data[4][0..4], [true, true, true, true]);
+        assert_eq!(display.data[0][0..4], [1, 1, 1, 1]);
+        assert_eq!(display.data[1][0..4], [0, 0, 0, 1]);
+        assert_eq!(display.data[2][0..4], [1, 1, 1, 1]);
+        assert_eq!(display.data[3][0..4], [1, 0, 0, 0]);
+        assert_eq!(display.data[4][0..4], [1, 1, 1, 1]);
     }
 
     #[test]
     fn test_draw_with_collision() {
         let mut display = Display::new();
-        display.draw(0, 0, &[0xF0]);
-        let collision = display.draw(0, 0, &[0x90]);
+        display.draw(0, 0, &[0xF0], false);
+        let collision = display.draw(0, 0, &[0x90], false);
         assert!(collision);
-        assert_eq!(display.data[0][0..4], [false, true, true, false]);
+        assert_eq!(display.data[0][0..4], [0, 1, 1, 0]);
     }
 
     #[test]
     fn test_draw_wrapping() {
         let mut display = Display::new();
-        let collision = display.draw(WIDTH - 2, 0, &[0xF0]);
+        let collision = display.draw(WIDTH - 2, 0, &[0xF0], false);
+        assert!(!collision);
+        assert_eq!(display.data[0][WIDTH - 2..WIDTH], [1, 1]);
+        assert_eq!(display.data[0][0..2], [1, 1]);
+    }
+
+    #[test]
+    fn test_draw_clipping() {
+        let mut display = Display::new();
+        let collision = display.draw(WIDTH - 2, HEIGHT - 1, &[0xF0, 0xF0], true);
         assert!(!collision);
-        assert_eq!(display.data[0][WIDTH - 2..WIDTH], [true, true]);
-        assert_eq!(display.data[0][0..2], [true, true]);
+        assert_eq!(display.data[HEIGHT - 1][WIDTH - 2..WIDTH], [1, 1]);
+        assert_eq!(display.data[HEIGHT - 1][0..2], [0, 0]);
+        assert_eq!(display.data[0][WIDTH - 2..WIDTH], [0, 0]);
+    }
+
+    #[test]
+    fn test_draw_clipping_wraps_start_position() {
+        let mut display = Display::new();
+        display.draw(WIDTH + 1, HEIGHT, &[0x80], true);
+        assert_eq!(display.data[0][1], 1);
+    }
+
+    #[test]
+    fn test_set_hires() {
+        let mut display = Display::new();
+        display.draw(0, 0, &[0x80], false);
+        display.set_hires(true);
+        assert_eq!(
+            (display.width(), display.height()),
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        );
+        assert_eq!(display.data, vec![vec![0; HIRES_WIDTH]; HIRES_HEIGHT]);
+        display.draw(WIDTH, HEIGHT, &[0x80], false);
+        assert_eq!(display.data[HEIGHT][WIDTH], 1);
+        display.set_hires(false);
+        assert_eq!(display.data, vec![vec![0; WIDTH]; HEIGHT]);
+    }
+
+    #[test]
+    fn test_draw_large() {
+        let mut display = Display::new();
+        let mut sprite = [0; 32];
+        sprite[0] = 0x80;
+        sprite[31] = 0x01;
+        let collision = display.draw_large(0, 0, &sprite, false);
+        assert!(!collision);
+        assert_eq!(display.data[0][0], 1);
+        assert_eq!(display.data[15][15], 1);
+        assert_eq!(display.data[0][8], 0);
+    }
+
+    #[test]
+    fn test_scroll_down_and_up() {
+        let mut display = Display::new();
+        display.draw(0, 0, &[0x80], false);
+        display.scroll_down(3);
+        assert_eq!(display.data[0][0], 0);
+        assert_eq!(display.data[3][0], 1);
+        display.scroll_up(2);
+        assert_eq!(display.data[1][0], 1);
+        assert_eq!(display.data[3][0], 0);
+    }
+
+    #[test]
+    fn test_scroll_right_and_left() {
+        let mut display = Display::new();
+        display.draw(0, 0, &[0x80], false);
+        display.draw(WIDTH - 1, 1, &[0x80], false);
+        display.scroll_right(4);
+        assert_eq!(display.data[0][4], 1);
+        assert_eq!(display.data[1][3], 0);
+        display.scroll_left(4);
+        assert_eq!(display.data[0][0], 1);
+        assert_eq!(display.data[1][WIDTH - 1], 0);
+    }
+
+    #[test]
+    fn test_draw_both_planes() {
+        let mut display = Display::new();
+        display.select_planes(0b11);
+        let collision = display.draw(0, 0, &[0xC0, 0xA0], false);
+        assert!(!collision);
+        assert_eq!(display.data[0][0..3], [3, 1, 2]);
+    }
+
+    #[test]
+    fn test_plane_selection_limits_clear_and_scroll() {
+        let mut display = Display::new();
+        display.select_planes(0b11);
+        display.draw(0, 0, &[0x80, 0x80], false);
+        display.select_planes(0b10);
+        display.scroll_right(1);
+        assert_eq!(display.data[0][0..2], [1, 2]);
+        display.clear();
+        assert_eq!(display.data[0][0..2], [1, 0]);
+        display.select_planes(0b00);
+        assert!(!display.draw(0, 0, &[0x80], false));
+        assert_eq!(display.data[0][0], 1);
     }
 }
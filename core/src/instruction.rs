@@ -0,0 +1,459 @@
+use std::fmt;
+
+macro_rules! combine_nibbles {
+    ($($nibble:expr),+) => {
+        {
+            [$($nibble),+]
+                .iter()
+                .rev()
+                .enumerate()
+                .fold(0u32, |acc, (i, &v)| acc | ((v as u32) << (i * 4)))
+        }
+    };
+}
+
+fn split_byte(byte: u8) -> (u8, u8) {
+    (byte >> 4, byte & 0x0F)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00CN
+    ScrollDown(u8),
+    /// 00DN
+    ScrollUp(u8),
+    /// 00E0
+    ClearScreen,
+    /// 00EE
+    Return,
+    /// 00FB
+    ScrollRight,
+    /// 00FC
+    ScrollLeft,
+    /// 00FD
+    Exit,
+    /// 00FE
+    LowRes,
+    /// 00FF
+    HighRes,
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SkipEqImm { x: u8, nn: u8 },
+    /// 4XNN
+    SkipNeImm { x: u8, nn: u8 },
+    /// 5XY0
+    SkipEqReg { x: u8, y: u8 },
+    /// 5XY2
+    SaveRange { x: u8, y: u8 },
+    /// 5XY3
+    LoadRange { x: u8, y: u8 },
+    /// 6XNN
+    LoadImm { x: u8, nn: u8 },
+    /// 7XNN
+    AddImm { x: u8, nn: u8 },
+    /// 8XY0
+    LoadReg { x: u8, y: u8 },
+    /// 8XY1
+    Or { x: u8, y: u8 },
+    /// 8XY2
+    And { x: u8, y: u8 },
+    /// 8XY3
+    Xor { x: u8, y: u8 },
+    /// 8XY4
+    AddReg { x: u8, y: u8 },
+    /// 8XY5
+    SubReg { x: u8, y: u8 },
+    /// 8XY6
+    ShiftRight { x: u8, y: u8 },
+    /// 8XY7
+    SubN { x: u8, y: u8 },
+    /// 8XYE
+    ShiftLeft { x: u8, y: u8 },
+    /// 9XY0
+    SkipNeReg { x: u8, y: u8 },
+    /// ANNN
+    LoadIndex(u16),
+    /// BNNN
+    JumpOffset(u16),
+    /// CXNN
+    Random { x: u8, nn: u8 },
+    /// DXYN
+    Draw { x: u8, y: u8, n: u8 },
+    /// EX9E
+    SkipKeyPressed { x: u8 },
+    /// EXA1
+    SkipKeyNotPressed { x: u8 },
+    /// F000 NNNN
+    LoadLongIndex(u16),
+    /// FN01
+    SelectPlane(u8),
+    /// F002
+    LoadAudio,
+    /// FX07
+    LoadDelay { x: u8 },
+    /// FX0A
+    WaitKey { x: u8 },
+    /// FX15
+    SetDelay { x: u8 },
+    /// FX18
+    SetSound { x: u8 },
+    /// FX1E
+    AddIndex { x: u8 },
+    /// FX29
+    LoadFont { x: u8 },
+    /// FX30
+    LoadBigFont { x: u8 },
+    /// FX3A
+    SetPitch { x: u8 },
+    /// FX33
+    StoreBcd { x: u8 },
+    /// FX55
+    StoreRegs { x: u8 },
+    /// FX65
+    LoadRegs { x: u8 },
+    /// FX75
+    StoreFlags { x: u8 },
+    /// FX85
+    LoadFlags { x: u8 },
+}
+
+impl Instruction {
+    // F000 NNNN is the only instruction that takes two words
+    pub fn is_long(opcode: u16) -> bool {
+        opcode == 0xF000
+    }
+
+    pub fn decode_long(opcode: u16, operand: u16) -> Option<Self> {
+        if Self::is_long(opcode) {
+            Some(Self::LoadLongIndex(operand))
+        } else {
+            Self::decode(opcode)
+        }
+    }
+
+    pub fn decode(opcode: u16) -> Option<Self> {
+        let [hi, lo] = opcode.to_be_bytes();
+        let (a, x) = split_byte(hi);
+        let (y, n) = split_byte(lo);
+        let nnn = opcode & 0x0FFF;
+        let instruction = match (a, x, y, n) {
+            (0x0, 0x0, 0xC, _) => Self::ScrollDown(n),
+            (0x0, 0x0, 0xD, _) => Self::ScrollUp(n),
+            (0x0, 0x0, 0xE, 0x0) => Self::ClearScreen,
+            (0x0, 0x0, 0xE, 0xE) => Self::Return,
+            (0x0, 0x0, 0xF, 0xB) => Self::ScrollRight,
+            (0x0, 0x0, 0xF, 0xC) => Self::ScrollLeft,
+            (0x0, 0x0, 0xF, 0xD) => Self::Exit,
+            (0x0, 0x0, 0xF, 0xE) => Self::LowRes,
+            (0x0, 0x0, 0xF, 0xF) => Self::HighRes,
+            (0x1, _, _, _) => Self::Jump(nnn),
+            (0x2, _, _, _) => Self::Call(nnn),
+            (0x3, _, _, _) => Self::SkipEqImm { x, nn: lo },
+            (0x4, _, _, _) => Self::SkipNeImm { x, nn: lo },
+            (0x5, _, _, 0x0) => Self::SkipEqReg { x, y },
+            (0x5, _, _, 0x2) => Self::SaveRange { x, y },
+            (0x5, _, _, 0x3) => Self::LoadRange { x, y },
+            (0x6, _, _, _) => Self::LoadImm { x, nn: lo },
+            (0x7, _, _, _) => Self::AddImm { x, nn: lo },
+            (0x8, _, _, 0x0) => Self::LoadReg { x, y },
+            (0x8, _, _, 0x1) => Self::Or { x, y },
+            (0x8, _, _, 0x2) => Self::And { x, y },
+            (0x8, _, _, 0x3) => Self::Xor { x, y },
+            (0x8, _, _, 0x4) => Self::AddReg { x, y },
+            (0x8, _, _, 0x5) => Self::SubReg { x, y },
+            (0x8, _, _, 0x6) => Self::ShiftRight { x, y },
+            (0x8, _, _, 0x7) => Self::SubN { x, y },
+            (0x8, _, _, 0xE) => Self::ShiftLeft { x, y },
+            (0x9, _, _, 0x0) => Self::SkipNeReg { x, y },
+            (0xA, _, _, _) => Self::LoadIndex(nnn),
+            (0xB, _, _, _) => Self::JumpOffset(nnn),
+            (0xC, _, _, _) => Self::Random { x, nn: lo },
+            (0xD, _, _, _) => Self::Draw { x, y, n },
+            (0xE, _, 0x9, 0xE) => Self::SkipKeyPressed { x },
+            (0xE, _, 0xA, 0x1) => Self::SkipKeyNotPressed { x },
+            (0xF, _, 0x0, 0x1) => Self::SelectPlane(x),
+            (0xF, 0x0, 0x0, 0x2) => Self::LoadAudio,
+            (0xF, _, 0x0, 0x7) => Self::LoadDelay { x },
+            (0xF, _, 0x0, 0xA) => Self::WaitKey { x },
+            (0xF, _, 0x1, 0x5) => Self::SetDelay { x },
+            (0xF, _, 0x1, 0x8) => Self::SetSound { x },
+            (0xF, _, 0x1, 0xE) => Self::AddIndex { x },
+            (0xF, _, 0x2, 0x9) => Self::LoadFont { x },
+            (0xF, _, 0x3, 0x0) => Self::LoadBigFont { x },
+            (0xF, _, 0x3, 0xA) => Self::SetPitch { x },
+            (0xF, _, 0x3, 0x3) => Self::StoreBcd { x },
+            (0xF, _, 0x5, 0x5) => Self::StoreRegs { x },
+            (0xF, _, 0x6, 0x5) => Self::LoadRegs { x },
+            (0xF, _, 0x7, 0x5) => Self::StoreFlags { x },
+            (0xF, _, 0x8, 0x5) => Self::LoadFlags { x },
+            _ => return None,
+        };
+        Some(instruction)
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Self::LoadLongIndex(_) => 4,
+            _ => 2,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let Self::LoadLongIndex(nnnn) = self {
+            bytes.extend_from_slice(&nnnn.to_be_bytes());
+        }
+        bytes
+    }
+
+    // Returns the first word; see `to_bytes` for the operand of F000 NNNN
+    pub fn encode(&self) -> u16 {
+        let opcode = match *self {
+            Self::ScrollDown(n) => 0x00C0 | (n as u32 & 0xF),
+            Self::ScrollUp(n) => 0x00D0 | (n as u32 & 0xF),
+            Self::ClearScreen => 0x00E0,
+            Self::Return => 0x00EE,
+            Self::ScrollRight => 0x00FB,
+            Self::ScrollLeft => 0x00FC,
+            Self::Exit => 0x00FD,
+            Self::LowRes => 0x00FE,
+            Self::HighRes => 0x00FF,
+            Self::Jump(nnn) => 0x1000 | (nnn as u32 & 0xFFF),
+            Self::Call(nnn) => 0x2000 | (nnn as u32 & 0xFFF),
+            Self::SkipEqImm { x, nn } => combine_nibbles!(0x3, x, nn >> 4, nn & 0xF),
+            Self::SkipNeImm { x, nn } => combine_nibbles!(0x4, x, nn >> 4, nn & 0xF),
+            Self::SkipEqReg { x, y } => combine_nibbles!(0x5, x, y, 0x0),
+            Self::SaveRange { x, y } => combine_nibbles!(0x5, x, y, 0x2),
+            Self::LoadRange { x, y } => combine_nibbles!(0x5, x, y, 0x3),
+            Self::LoadImm { x, nn } => combine_nibbles!(0x6, x, nn >> 4, nn & 0xF),
+            Self::AddImm { x, nn } => combine_nibbles!(0x7, x, nn >> 4, nn & 0xF),
+            Self::LoadReg { x, y } => combine_nibbles!(0x8, x, y, 0x0),
+            Self::Or { x, y } => combine_nibbles!(0x8, x, y, 0x1),
+            Self::And { x, y } => combine_nibbles!(0x8, x, y, 0x2),
+            Self::Xor { x, y } => combine_nibbles!(0x8, x, y, 0x3),
+            Self::AddReg { x, y } => combine_nibbles!(0x8, x, y, 0x4),
+            Self::SubReg { x, y } => combine_nibbles!(0x8, x, y, 0x5),
+            Self::ShiftRight { x, y } => combine_nibbles!(0x8, x, y, 0x6),
+            Self::SubN { x, y } => combine_nibbles!(0x8, x, y, 0x7),
+            Self::ShiftLeft { x, y } => combine_nibbles!(0x8, x, y, 0xE),
+            Self::SkipNeReg { x, y } => combine_nibbles!(0x9, x, y, 0x0),
+            Self::LoadIndex(nnn) => 0xA000 | (nnn as u32 & 0xFFF),
+            Self::JumpOffset(nnn) => 0xB000 | (nnn as u32 & 0xFFF),
+            Self::Random { x, nn } => combine_nibbles!(0xC, x, nn >> 4, nn & 0xF),
+            Self::Draw { x, y, n } => combine_nibbles!(0xD, x, y, n),
+            Self::SkipKeyPressed { x } => combine_nibbles!(0xE, x, 0x9, 0xE),
+            Self::SkipKeyNotPressed { x } => combine_nibbles!(0xE, x, 0xA, 0x1),
+            Self::LoadLongIndex(_) => 0xF000,
+            Self::SelectPlane(n) => combine_nibbles!(0xF, n, 0x0, 0x1),
+            Self::LoadAudio => 0xF002,
+            Self::LoadDelay { x } => combine_nibbles!(0xF, x, 0x0, 0x7),
+            Self::WaitKey { x } => combine_nibbles!(0xF, x, 0x0, 0xA),
+            Self::SetDelay { x } => combine_nibbles!(0xF, x, 0x1, 0x5),
+            Self::SetSound { x } => combine_nibbles!(0xF, x, 0x1, 0x8),
+            Self::AddIndex { x } => combine_nibbles!(0xF, x, 0x1, 0xE),
+            Self::LoadFont { x } => combine_nibbles!(0xF, x, 0x2, 0x9),
+            Self::LoadBigFont { x } => combine_nibbles!(0xF, x, 0x3, 0x0),
+            Self::SetPitch { x } => combine_nibbles!(0xF, x, 0x3, 0xA),
+            Self::StoreBcd { x } => combine_nibbles!(0xF, x, 0x3, 0x3),
+            Self::StoreRegs { x } => combine_nibbles!(0xF, x, 0x5, 0x5),
+            Self::LoadRegs { x } => combine_nibbles!(0xF, x, 0x6, 0x5),
+            Self::StoreFlags { x } => combine_nibbles!(0xF, x, 0x7, 0x5),
+            Self::LoadFlags { x } => combine_nibbles!(0xF, x, 0x8, 0x5),
+        };
+        opcode as u16
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::ScrollDown(n) => write!(f, "SCD {}", n),
+            Self::ScrollUp(n) => write!(f, "SCU {}", n),
+            Self::ClearScreen => write!(f, "CLS"),
+            Self::Return => write!(f, "RET"),
+            Self::ScrollRight => write!(f, "SCR"),
+            Self::ScrollLeft => write!(f, "SCL"),
+            Self::Exit => write!(f, "EXIT"),
+            Self::LowRes => write!(f, "LOW"),
+            Self::HighRes => write!(f, "HIGH"),
+            Self::Jump(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Self::Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Self::SkipEqImm { x, nn } => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            Self::SkipNeImm { x, nn } => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            Self::SkipEqReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Self::SaveRange { x, y } => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Self::LoadRange { x, y } => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Self::LoadImm { x, nn } => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            Self::AddImm { x, nn } => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            Self::LoadReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Self::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Self::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Self::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Self::AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Self::SubReg { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Self::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Self::SubN { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Self::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Self::SkipNeReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Self::LoadIndex(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            Self::JumpOffset(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Self::Random { x, nn } => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Self::Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Self::SkipKeyPressed { x } => write!(f, "SKP V{:X}", x),
+            Self::SkipKeyNotPressed { x } => write!(f, "SKNP V{:X}", x),
+            Self::LoadLongIndex(nnnn) => write!(f, "LD I, LONG 0x{:04X}", nnnn),
+            Self::SelectPlane(n) => write!(f, "PLANE {}", n),
+            Self::LoadAudio => write!(f, "AUDIO"),
+            Self::LoadDelay { x } => write!(f, "LD V{:X}, DT", x),
+            Self::WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            Self::SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            Self::SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            Self::AddIndex { x } => write!(f, "ADD I, V{:X}", x),
+            Self::LoadFont { x } => write!(f, "LD F, V{:X}", x),
+            Self::LoadBigFont { x } => write!(f, "LD HF, V{:X}", x),
+            Self::SetPitch { x } => write!(f, "PITCH V{:X}", x),
+            Self::StoreBcd { x } => write!(f, "LD B, V{:X}", x),
+            Self::StoreRegs { x } => write!(f, "LD [I], V{:X}", x),
+            Self::LoadRegs { x } => write!(f, "LD V{:X}, [I]", x),
+            Self::StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            Self::LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Instruction, split_byte};
+
+    #[test]
+    fn test_combine_nibbles() {
+        assert_eq!(combine_nibbles!(0xA, 0xB, 0xC), 0xABC);
+    }
+
+    #[test]
+    fn test_split_byte() {
+        assert_eq!(split_byte(0xAB), (0xA, 0xB));
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Instruction::decode(0x00E0), Some(Instruction::ClearScreen));
+        assert_eq!(Instruction::decode(0x00EE), Some(Instruction::Return));
+        assert_eq!(Instruction::decode(0x1234), Some(Instruction::Jump(0x234)));
+        assert_eq!(
+            Instruction::decode(0x3AEE),
+            Some(Instruction::SkipEqImm { x: 0xA, nn: 0xEE })
+        );
+        assert_eq!(
+            Instruction::decode(0xD125),
+            Some(Instruction::Draw { x: 1, y: 2, n: 5 })
+        );
+        assert_eq!(
+            Instruction::decode(0xF365),
+            Some(Instruction::LoadRegs { x: 3 })
+        );
+    }
+
+    #[test]
+    fn test_decode_super_chip() {
+        assert_eq!(
+            Instruction::decode(0x00C5),
+            Some(Instruction::ScrollDown(5))
+        );
+        assert_eq!(Instruction::decode(0x00FB), Some(Instruction::ScrollRight));
+        assert_eq!(Instruction::decode(0x00FC), Some(Instruction::ScrollLeft));
+        assert_eq!(Instruction::decode(0x00FD), Some(Instruction::Exit));
+        assert_eq!(Instruction::decode(0x00FE), Some(Instruction::LowRes));
+        assert_eq!(Instruction::decode(0x00FF), Some(Instruction::HighRes));
+        assert_eq!(
+            Instruction::decode(0xF230),
+            Some(Instruction::LoadBigFont { x: 2 })
+        );
+        assert_eq!(
+            Instruction::decode(0xF775),
+            Some(Instruction::StoreFlags { x: 7 })
+        );
+        assert_eq!(
+            Instruction::decode(0xF785),
+            Some(Instruction::LoadFlags { x: 7 })
+        );
+    }
+
+    #[test]
+    fn test_decode_xo_chip() {
+        assert_eq!(Instruction::decode(0x00D3), Some(Instruction::ScrollUp(3)));
+        assert_eq!(
+            Instruction::decode(0x5122),
+            Some(Instruction::SaveRange { x: 1, y: 2 })
+        );
+        assert_eq!(
+            Instruction::decode(0x5213),
+            Some(Instruction::LoadRange { x: 2, y: 1 })
+        );
+        assert_eq!(
+            Instruction::decode(0xF301),
+            Some(Instruction::SelectPlane(3))
+        );
+        assert_eq!(Instruction::decode(0xF002), Some(Instruction::LoadAudio));
+        assert_eq!(
+            Instruction::decode(0xF43A),
+            Some(Instruction::SetPitch { x: 4 })
+        );
+        assert_eq!(Instruction::decode(0xF000), None);
+        assert_eq!(
+            Instruction::decode_long(0xF000, 0x1234),
+            Some(Instruction::LoadLongIndex(0x1234))
+        );
+        assert_eq!(
+            Instruction::decode_long(0x00E0, 0x1234),
+            Some(Instruction::ClearScreen)
+        );
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        assert_eq!(Instruction::Jump(0x234).to_bytes(), [0x12, 0x34]);
+        assert_eq!(
+            Instruction::LoadLongIndex(0xABCD).to_bytes(),
+            [0xF0, 0x00, 0xAB, 0xCD]
+        );
+        assert_eq!(Instruction::LoadLongIndex(0xABCD).size(), 4);
+    }
+
+    #[test]
+    fn test_decode_unknown() {
+        assert_eq!(Instruction::decode(0x0000), None);
+        assert_eq!(Instruction::decode(0x5121), None);
+        assert_eq!(Instruction::decode(0x800F), None);
+        assert_eq!(Instruction::decode(0xE19F), None);
+        assert_eq!(Instruction::decode(0xF0FF), None);
+        assert_eq!(Instruction::decode(0xF102), None);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        for opcode in 0..=u16::MAX {
+            if let Some(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Instruction::Jump(0x200).to_string(), "JP 0x200");
+        assert_eq!(
+            Instruction::SkipEqImm { x: 0xA, nn: 0xEE }.to_string(),
+            "SE VA, 0xEE"
+        );
+        assert_eq!(
+            Instruction::Draw { x: 0, y: 1, n: 5 }.to_string(),
+            "DRW V0, V1, 5"
+        );
+        assert_eq!(Instruction::JumpOffset(0x300).to_string(), "JP V0, 0x300");
+        assert_eq!(Instruction::StoreRegs { x: 2 }.to_string(), "LD [I], V2");
+    }
+}
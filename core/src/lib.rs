@@ -1,40 +1,163 @@
-use crate::cpu::Cpu;
-use crate::display::{Display, HEIGHT, WIDTH};
-use crate::memory::{MEMORY_LEN, Memory};
+pub use crate::cpu::{Cpu, CpuFault, KeyWait, Registers, StepOutcome};
+pub use crate::disasm::{Disassembly, Line, disassemble};
+use crate::display::{Display, HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, WIDTH};
+pub use crate::instruction::Instruction;
+use crate::memory::Memory;
+pub use crate::memory::PROGRAM_START;
+pub use crate::platform::Platform;
+pub use crate::quirks::Quirks;
+pub use crate::random::{RandomSource, SplitMix64};
+use crate::savestate::{MAGIC, StateReader, StateWriter, VERSION};
+pub use crate::savestate::{StateError, rom_hash};
+use crate::trace::Fetched;
+pub use crate::trace::Tracer;
+use crate::watch::Snapshot;
+pub use crate::watch::{StopReason, Watchpoint, parse_range};
+pub mod asm;
 mod cpu;
+mod disasm;
 mod display;
+mod instruction;
 mod memory;
+mod platform;
+mod quirks;
+mod random;
+mod savestate;
+mod trace;
+mod watch;
 
 pub const DISPLAY_WIDTH: usize = WIDTH;
 pub const DISPLAY_HEIGHT: usize = HEIGHT;
+pub const DISPLAY_HIRES_WIDTH: usize = HIRES_WIDTH;
+pub const DISPLAY_HIRES_HEIGHT: usize = HIRES_HEIGHT;
 
 pub struct Chip8 {
     pub cpu: Cpu,
     pub memory: Memory,
     pub display: Display,
+    rng: Box<dyn RandomSource>,
+    rom_hash: u64,
+    watchpoints: Vec<Watchpoint>,
+    tracer: Option<Tracer>,
 }
 
 impl Chip8 {
-    pub fn new(program: &[u8]) -> Self {
+    pub fn new(program: &[u8], platform: Platform, quirks: Quirks) -> Self {
         Self {
-            cpu: Cpu::new(),
-            memory: Memory::new(program),
+            cpu: Cpu::new(quirks),
+            memory: Memory::new(program, platform.memory_len()),
             display: Display::new(),
+            rng: Box::new(SplitMix64::new(rand::random())),
+            rom_hash: rom_hash(program),
+            watchpoints: Vec::new(),
+            tracer: None,
         }
     }
 
-    pub fn step(&mut self, key: u16) {
-        self.cpu.step(&mut self.memory, &mut self.display, key);
+    // Replaces the entropy-seeded default, e.g. with `SplitMix64::new(seed)` for reproducible runs
+    pub fn set_random_source(&mut self, rng: impl RandomSource + 'static) {
+        self.rng = Box::new(rng);
     }
 
-    pub fn get_memory(&self) -> &[u8; MEMORY_LEN] {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.put_bytes(MAGIC);
+        writer.put_u16(VERSION);
+        writer.put_u64(self.rom_hash);
+        self.cpu.save(&mut writer);
+        self.display.save(&mut writer);
+        self.memory.save(&mut writer);
+        writer.put_u64(self.rng.state());
+        writer.into_bytes()
+    }
+
+    // The machine is left untouched if the state cannot be loaded
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(state);
+        if reader.bytes(MAGIC.len()) != Ok(MAGIC) {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if reader.u64()? != self.rom_hash {
+            return Err(StateError::RomMismatch);
+        }
+        let cpu = Cpu::load(&mut reader)?;
+        let display = Display::load(&mut reader)?;
+        let memory = Memory::load(&mut reader, self.memory.data.len())?;
+        let rng_state = reader.u64()?;
+        if !reader.is_empty() {
+            return Err(StateError::Invalid("length"));
+        }
+        self.cpu = cpu;
+        self.display = display;
+        self.memory = memory;
+        self.rng.set_state(rng_state);
+        Ok(())
+    }
+
+    pub fn step(&mut self, key: u16) -> Result<StepOutcome, CpuFault> {
+        if self.watchpoints.is_empty() && self.tracer.is_none() {
+            return self
+                .cpu
+                .step(&mut self.memory, &mut self.display, self.rng.as_mut(), key);
+        }
+        let before = Snapshot::new(&self.cpu);
+        let fetched = self
+            .tracer
+            .is_some()
+            .then(|| Fetched::new(&self.memory.data, self.cpu.get_pc()));
+        let outcome = self
+            .cpu
+            .step(&mut self.memory, &mut self.display, self.rng.as_mut(), key)?;
+        if let (Some(tracer), Some(fetched)) = (&mut self.tracer, &fetched) {
+            tracer.record(fetched, &self.cpu, outcome);
+        }
+        if outcome != StepOutcome::Executed {
+            return Ok(outcome);
+        }
+        let stop = self
+            .watchpoints
+            .iter()
+            .find_map(|watchpoint| watchpoint.check(&before, &self.cpu));
+        Ok(stop.map_or(outcome, StepOutcome::Stopped))
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Watchpoint {
+        self.watchpoints.remove(index)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Returns the previous tracer so that it can be finished
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    pub fn get_memory(&self) -> &[u8] {
         &self.memory.data
     }
 
-    pub fn get_display(&self) -> &[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT] {
+    pub fn get_display(&self) -> &[Vec<u8>] {
         &self.display.data
     }
 
+    pub fn registers(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    pub fn vblank(&mut self) {
+        self.cpu.vblank();
+    }
+
     pub fn dec_delay_timer(&mut self) {
         self.cpu.dec_delay_timer();
     }
@@ -46,4 +169,111 @@ impl Chip8 {
     pub fn get_sound_timer(&self) -> u8 {
         self.cpu.get_sound_timer()
     }
+
+    pub fn get_audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.cpu.get_audio_pattern()
+    }
+
+    pub fn get_pitch(&self) -> u8 {
+        self.cpu.get_pitch()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chip8, KeyWait, Platform, Quirks, SplitMix64, StateError, StepOutcome};
+
+    #[test]
+    fn test_save_and_load_state() {
+        let program = [0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06];
+        let mut chip8 = Chip8::new(&program, Platform::Chip8, Quirks::default());
+        for _ in 0..3 {
+            chip8.step(0).unwrap();
+        }
+        let state = chip8.save_state();
+        assert_eq!(&state[0..4], b"C8SS");
+
+        let mut restored = Chip8::new(&program, Platform::Chip8, Quirks::default());
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.get_display(), chip8.get_display());
+        assert_eq!(restored.get_memory(), chip8.get_memory());
+        assert_eq!(restored.save_state(), state);
+    }
+
+    #[test]
+    fn test_save_state_keeps_key_wait() {
+        let quirks = Quirks {
+            key_release: true,
+            ..Quirks::default()
+        };
+        let mut chip8 = Chip8::new(&[0xF6, 0x0A], Platform::Chip8, quirks);
+        chip8.step(0).unwrap();
+        chip8.step(0b100).unwrap();
+        let state = chip8.save_state();
+
+        let mut restored = Chip8::new(&[0xF6, 0x0A], Platform::Chip8, quirks);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.cpu.get_key_wait(), Some(KeyWait::Release(2)));
+        assert_eq!(restored.step(0), Ok(StepOutcome::Executed));
+        assert_eq!(restored.registers().v[6], 2);
+    }
+
+    #[test]
+    fn test_load_state_errors() {
+        let mut chip8 = Chip8::new(&[0x12, 0x00], Platform::Chip8, Quirks::default());
+        let state = chip8.save_state();
+
+        assert_eq!(chip8.load_state(b"nope"), Err(StateError::BadMagic));
+        let mut future = state.clone();
+        future[5] = 1;
+        assert_eq!(
+            chip8.load_state(&future),
+            Err(StateError::UnsupportedVersion(1))
+        );
+        assert_eq!(
+            chip8.load_state(&state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        );
+        let mut other = Chip8::new(&[0x12, 0x02], Platform::Chip8, Quirks::default());
+        assert_eq!(other.load_state(&state), Err(StateError::RomMismatch));
+        let mut xo = Chip8::new(&[0x12, 0x00], Platform::XoChip, Quirks::default());
+        assert_eq!(
+            xo.load_state(&state),
+            Err(StateError::Invalid("memory size"))
+        );
+    }
+
+    #[test]
+    fn test_seeded_runs_are_reproducible() {
+        // Draws a random byte-wide sprite row at a random position, forever
+        let program = [
+            0xC0, 0xFF, 0xC1, 0x3F, 0xC2, 0x1F, 0xA2, 0x0E, 0xF0, 0x55, 0xD1, 0x21, 0x12, 0x00,
+            0x00,
+        ];
+        let run = |seed| {
+            let mut chip8 = Chip8::new(&program, Platform::Chip8, Quirks::default());
+            chip8.set_random_source(SplitMix64::new(seed));
+            for _ in 0..700 {
+                chip8.step(0).unwrap();
+            }
+            chip8.get_display().to_vec()
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    fn test_save_state_restores_rng() {
+        let program = [0xC0, 0xFF, 0x12, 0x00];
+        let mut chip8 = Chip8::new(&program, Platform::Chip8, Quirks::default());
+        chip8.set_random_source(SplitMix64::new(7));
+        let state = chip8.save_state();
+        chip8.step(0).unwrap();
+        let first = chip8.save_state();
+        chip8.step(0).unwrap();
+        chip8.step(0).unwrap();
+        chip8.load_state(&state).unwrap();
+        chip8.step(0).unwrap();
+        assert_eq!(chip8.save_state(), first);
+    }
 }
@@ -1,12 +1,16 @@
+use crate::savestate::{StateError, StateReader, StateWriter};
+
 pub const MEMORY_LEN: usize = 0x1000;
+pub const XO_MEMORY_LEN: usize = 0x10000;
 pub const PROGRAM_START: usize = 0x200;
+pub const BIG_FONT_START: usize = 0x50;
 
 pub struct Memory {
-    pub data: [u8; MEMORY_LEN],
+    pub data: Vec<u8>,
 }
 
 impl Memory {
-    pub fn new(program: &[u8]) -> Self {
+    pub fn new(program: &[u8], len: usize) -> Self {
         let font = [
             0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
             0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -25,9 +29,42 @@ impl Memory {
             0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
             0xF0, 0x80, 0xF0, 0x80, 0x80, // F
         ];
-        let mut data = [0; MEMORY_LEN];
+        let big_font = [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ];
+        let mut data = vec![0; len];
         data[..font.len()].copy_from_slice(&font);
+        data[BIG_FONT_START..(BIG_FONT_START + big_font.len())].copy_from_slice(&big_font);
         data[PROGRAM_START..(PROGRAM_START + program.len())].copy_from_slice(program);
         Self { data }
     }
+
+    pub fn save(&self, writer: &mut StateWriter) {
+        writer.put_u32(self.data.len() as u32);
+        writer.put_bytes(&self.data);
+    }
+
+    pub fn load(reader: &mut StateReader, len: usize) -> Result<Self, StateError> {
+        if reader.u32()? as usize != len {
+            return Err(StateError::Invalid("memory size"));
+        }
+        Ok(Self {
+            data: reader.bytes(len)?.to_vec(),
+        })
+    }
 }
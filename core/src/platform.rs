@@ -0,0 +1,42 @@
+use crate::memory::{MEMORY_LEN, PROGRAM_START, XO_MEMORY_LEN};
+use crate::quirks::Quirks;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn memory_len(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => MEMORY_LEN,
+            Platform::XoChip => XO_MEMORY_LEN,
+        }
+    }
+
+    pub fn max_program_len(self) -> usize {
+        self.memory_len() - PROGRAM_START
+    }
+
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::default(),
+            Platform::SuperChip => Quirks::SUPER_CHIP,
+            Platform::XoChip => Quirks::XO_CHIP,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Platform;
+
+    #[test]
+    fn test_max_program_len() {
+        assert_eq!(Platform::Chip8.max_program_len(), 0xE00);
+        assert_eq!(Platform::SuperChip.max_program_len(), 0xE00);
+        assert_eq!(Platform::XoChip.max_program_len(), 0xFE00);
+    }
+}
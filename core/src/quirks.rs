@@ -0,0 +1,145 @@
+/// Behaviors of ambiguous opcodes that differ between CHIP-8 interpreters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place instead of shifting VY into VX.
+    pub shift: bool,
+    /// FX55/FX65 increment I by X + 1.
+    pub memory_increment: bool,
+    /// BNNN jumps to XNN + VX instead of NNN + V0.
+    pub jump_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clipping: bool,
+    /// DXYN waits for the next vertical blank before drawing.
+    pub display_wait: bool,
+    /// FX0A completes when the key is released rather than when it is pressed.
+    pub key_release: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub const VIP: Self = Self {
+        shift: false,
+        memory_increment: true,
+        jump_vx: false,
+        vf_reset: true,
+        clipping: true,
+        display_wait: true,
+        key_release: true,
+    };
+
+    /// SUPER-CHIP 1.1 on the HP 48.
+    pub const SUPER_CHIP: Self = Self {
+        shift: true,
+        memory_increment: false,
+        jump_vx: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+        key_release: false,
+    };
+
+    /// XO-CHIP as implemented by Octo.
+    pub const XO_CHIP: Self = Self {
+        shift: false,
+        memory_increment: true,
+        jump_vx: false,
+        vf_reset: false,
+        clipping: false,
+        display_wait: false,
+        key_release: true,
+    };
+}
+
+const NAMES: [&str; 7] = [
+    "shift",
+    "memory_increment",
+    "jump_vx",
+    "vf_reset",
+    "clipping",
+    "display_wait",
+    "key_release",
+];
+
+impl Quirks {
+    /// Names of the quirks set differently in `other`, in bit order.
+    pub fn differences(self, other: Self) -> Vec<&'static str> {
+        let changed = self.to_bits() ^ other.to_bits();
+        NAMES
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| changed & (1 << i) != 0)
+            .map(|(_, &name)| name)
+            .collect()
+    }
+
+    pub fn to_bits(self) -> u8 {
+        [
+            self.shift,
+            self.memory_increment,
+            self.jump_vx,
+            self.vf_reset,
+            self.clipping,
+            self.display_wait,
+            self.key_release,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, &flag)| acc | ((flag as u8) << i))
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        let flag = |i: u8| bits & (1 << i) != 0;
+        Self {
+            shift: flag(0),
+            memory_increment: flag(1),
+            jump_vx: flag(2),
+            vf_reset: flag(3),
+            clipping: flag(4),
+            display_wait: flag(5),
+            key_release: flag(6),
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift: true,
+            memory_increment: false,
+            jump_vx: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+            key_release: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Quirks;
+
+    #[test]
+    fn test_bits_round_trip() {
+        for quirks in [
+            Quirks::default(),
+            Quirks::VIP,
+            Quirks::SUPER_CHIP,
+            Quirks::XO_CHIP,
+        ] {
+            assert_eq!(Quirks::from_bits(quirks.to_bits()), quirks);
+        }
+        assert_eq!(Quirks::VIP.to_bits(), 0b1111010);
+    }
+
+    #[test]
+    fn test_differences() {
+        assert_eq!(
+            Quirks::VIP.differences(Quirks::XO_CHIP),
+            ["vf_reset", "clipping", "display_wait"]
+        );
+        assert!(Quirks::VIP.differences(Quirks::VIP).is_empty());
+    }
+}
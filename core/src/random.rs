@@ -0,0 +1,67 @@
+/// Source of the random bytes used by CXNN.
+///
+/// The state is a single `u64` so that it can be stored in save states.
+pub trait RandomSource {
+    fn next_u8(&mut self) -> u8;
+    fn state(&self) -> u64;
+    fn set_state(&mut self, state: u64);
+}
+
+/// SplitMix64, the default generator. Any seed is valid, including 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl RandomSource for SplitMix64 {
+    fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RandomSource, SplitMix64};
+
+    #[test]
+    fn test_split_mix_64() {
+        let mut rng = SplitMix64::new(0);
+        assert_eq!(rng.next_u64(), 0xE220A8397B1DCDAF);
+        assert_eq!(rng.next_u64(), 0x6E789E6AA1B965F4);
+    }
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = SplitMix64::new(42);
+        let mut b = SplitMix64::new(42);
+        let bytes: Vec<u8> = (0..32).map(|_| a.next_u8()).collect();
+        assert_eq!(bytes, (0..32).map(|_| b.next_u8()).collect::<Vec<_>>());
+
+        let state = a.state();
+        let next = a.next_u8();
+        b.set_state(state);
+        assert_eq!(b.next_u8(), next);
+    }
+}
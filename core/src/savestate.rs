@@ -0,0 +1,197 @@
+//! Binary save state format.
+//!
+//! All integers are big-endian. Version 3 is laid out as:
+//!
+//! | Field        | Size              | Notes                                    |
+//! |--------------|-------------------|------------------------------------------|
+//! | magic        | 4                 | `C8SS`                                   |
+//! | version      | 2                 | currently 3                              |
+//! | ROM hash     | 8                 | FNV-1a 64 of the loaded program          |
+//! | quirks       | 1                 | bit flags, see `Quirks::to_bits`         |
+//! | V0-VF        | 16                |                                          |
+//! | PC           | 4                 |                                          |
+//! | stack        | 16 * 4            |                                          |
+//! | SP           | 1                 | at most 16                               |
+//! | I            | 4                 |                                          |
+//! | DT, ST       | 1 + 1             |                                          |
+//! | RPL flags    | 16                |                                          |
+//! | audio        | 1 + 16            | pattern present flag, then the pattern   |
+//! | pitch        | 1                 |                                          |
+//! | vblank       | 1                 |                                          |
+//! | FX0A phase   | 1 + 2             | none, press with the held keys, or       |
+//! |              |                   | release with the key                     |
+//! | hires        | 1                 |                                          |
+//! | plane mask   | 1                 |                                          |
+//! | pixels       | width * height    | one palette index per pixel, row major   |
+//! | memory len   | 4                 | must match the running platform          |
+//! | memory       | memory len        |                                          |
+//! | RNG state    | 8                 | see `RandomSource::state`                |
+
+use std::fmt;
+
+pub const MAGIC: &[u8; 4] = b"C8SS";
+pub const VERSION: u16 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    RomMismatch,
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported save state version: {}", version)
+            }
+            StateError::RomMismatch => write!(f, "Save state belongs to a different ROM"),
+            StateError::Truncated => write!(f, "Save state is truncated"),
+            StateError::Invalid(field) => write!(f, "Save state has an invalid {}", field),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// FNV-1a 64 hash of a program, which save states use to identify their ROM
+pub fn rom_hash(program: &[u8]) -> u64 {
+    program.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn put_bool(&mut self, value: bool) {
+        self.put_u8(value as u8);
+    }
+
+    pub fn put_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rom_hash() {
+        assert_eq!(rom_hash(&[]), 0xcbf29ce484222325);
+        assert_eq!(rom_hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_ne!(rom_hash(&[0x12, 0x00]), rom_hash(&[0x12, 0x02]));
+    }
+
+    #[test]
+    fn test_writer_and_reader() {
+        let mut writer = StateWriter::new();
+        writer.put_u8(0xAB);
+        writer.put_bool(true);
+        writer.put_u16(0x1234);
+        writer.put_u32(0x89ABCDEF);
+        writer.put_u64(0x0102030405060708);
+        writer.put_bytes(&[9, 8, 7]);
+        let bytes = writer.into_bytes();
+
+        let mut reader = StateReader::new(&bytes);
+        assert_eq!(reader.u8(), Ok(0xAB));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0x1234));
+        assert_eq!(reader.u32(), Ok(0x89ABCDEF));
+        assert_eq!(reader.u64(), Ok(0x0102030405060708));
+        assert_eq!(reader.array(), Ok([9, 8, 7]));
+        assert!(reader.is_empty());
+        assert_eq!(reader.u8(), Err(StateError::Truncated));
+    }
+
+    #[test]
+    fn test_invalid_flag() {
+        let mut reader = StateReader::new(&[2]);
+        assert_eq!(reader.bool(), Err(StateError::Invalid("flag")));
+    }
+}
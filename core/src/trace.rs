@@ -0,0 +1,201 @@
+//! Execution tracing.
+//!
+//! Each executed instruction produces one line, with the registers as they
+//! are after it ran:
+//!
+//! ```text
+//! 0000000001 PC=0200 OP=6005 V=05000000000000000000000000000000 I=0000 SP=00 DT=00 ST=00 W=--------- ; LD V0, 0x05
+//! ```
+//!
+//! The cycle count is in decimal and counts every step, including those spent
+//! waiting for a key or vblank, which are not traced. `V` holds V0 to VF in
+//! order. `W` is the range of memory the instruction wrote, e.g.
+//! `W=0300-0302` for FX33. Everything before ` ; ` has a fixed width, so
+//! traces from different emulators can be compared after cutting off the
+//! mnemonic. For F000 NNNN, `OP` is the first word.
+
+use crate::cpu::{Cpu, StepOutcome};
+use crate::instruction::Instruction;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+/// Writes a line per executed instruction, optionally only for those at
+/// addresses within `ranges`
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    ranges: Vec<RangeInclusive<usize>>,
+    cycle: u64,
+    error: Option<io::Error>,
+    line: String,
+}
+
+// The instruction about to run, read before it can modify itself
+pub(crate) struct Fetched {
+    pc: usize,
+    opcode: u16,
+    instruction: Option<Instruction>,
+}
+
+impl Fetched {
+    pub(crate) fn new(memory: &[u8], pc: usize) -> Self {
+        let word = |addr: usize| {
+            let bytes = memory.get(addr..addr + 2)?;
+            Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+        };
+        let opcode = word(pc).unwrap_or_default();
+        let operand = if Instruction::is_long(opcode) {
+            word(pc + 2).unwrap_or_default()
+        } else {
+            0
+        };
+        Self {
+            pc,
+            opcode,
+            instruction: Instruction::decode_long(opcode, operand),
+        }
+    }
+}
+
+impl Tracer {
+    pub fn new(writer: impl Write + 'static, ranges: Vec<RangeInclusive<usize>>) -> Self {
+        Self {
+            writer: Box::new(writer),
+            ranges,
+            cycle: 0,
+            error: None,
+            line: String::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, fetched: &Fetched, cpu: &Cpu, outcome: StepOutcome) {
+        self.cycle += 1;
+        if outcome == StepOutcome::Waiting || self.error.is_some() {
+            return;
+        }
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|r| r.contains(&fetched.pc)) {
+            return;
+        }
+        self.line.clear();
+        let _ = write!(
+            self.line,
+            "{:010} PC={:04X} OP={:04X} V=",
+            self.cycle, fetched.pc, fetched.opcode
+        );
+        for value in cpu.get_v() {
+            let _ = write!(self.line, "{:02X}", value);
+        }
+        let _ = write!(
+            self.line,
+            " I={:04X} SP={:02X} DT={:02X} ST={:02X} ",
+            cpu.get_i() & 0xFFFF,
+            cpu.get_sp(),
+            cpu.get_delay_timer(),
+            cpu.get_sound_timer()
+        );
+        match cpu.get_last_write() {
+            Some((first, last)) => {
+                let _ = write!(self.line, "W={:04X}-{:04X} ; ", first, last);
+            }
+            None => self.line.push_str("W=--------- ; "),
+        }
+        match &fetched.instruction {
+            Some(instruction) => {
+                let _ = writeln!(self.line, "{}", instruction);
+            }
+            None => self.line.push_str("???\n"),
+        }
+        if let Err(e) = self.writer.write_all(self.line.as_bytes()) {
+            self.error = Some(e);
+        }
+    }
+
+    // Flushes the output and reports the first write error, after which tracing stopped
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chip8, Platform, Quirks};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Collects the output so the test can read it back after the tracer is gone
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(program: &[u8], ranges: Vec<RangeInclusive<usize>>, steps: usize) -> Vec<String> {
+        let buffer = Buffer::default();
+        let mut chip8 = Chip8::new(program, Platform::XoChip, Quirks::default());
+        chip8.set_tracer(Some(Tracer::new(buffer.clone(), ranges)));
+        for _ in 0..steps {
+            chip8.step(0).unwrap();
+        }
+        chip8.set_tracer(None).unwrap().finish().unwrap();
+        let text = String::from_utf8(buffer.0.take()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_line_format() {
+        // LD V0, 5; LD I, LONG 0x1234; CALL 0x20A; ...; RET
+        let program = [
+            0x60, 0x05, 0xF0, 0x00, 0x12, 0x34, 0x22, 0x0A, 0x00, 0x00, 0x00, 0xEE,
+        ];
+        let lines = trace(&program, Vec::new(), 4);
+        assert_eq!(
+            lines,
+            [
+                "0000000001 PC=0200 OP=6005 V=05000000000000000000000000000000 I=0000 SP=00 DT=00 ST=00 W=--------- ; LD V0, 0x05",
+                "0000000002 PC=0202 OP=F000 V=05000000000000000000000000000000 I=1234 SP=00 DT=00 ST=00 W=--------- ; LD I, LONG 0x1234",
+                "0000000003 PC=0206 OP=220A V=05000000000000000000000000000000 I=1234 SP=01 DT=00 ST=00 W=--------- ; CALL 0x20A",
+                "0000000004 PC=020A OP=00EE V=05000000000000000000000000000000 I=1234 SP=00 DT=00 ST=00 W=--------- ; RET",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ranges_and_waiting() {
+        // LD V1, 1; LD V0, K; JP 0x200
+        let program = [0x61, 0x01, 0xF0, 0x0A, 0x12, 0x00];
+        let lines = trace(&program, vec![0x200..=0x201], 3);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("0000000001 PC=0200"));
+        // The cycles spent waiting are counted but not traced
+        let lines = trace(&program, Vec::new(), 3);
+        assert_eq!(lines.len(), 1);
+    }
+
+    #[test]
+    fn test_memory_writes() {
+        // LD I, 0x300; LD B, V0
+        let lines = trace(&[0xA3, 0x00, 0xF0, 0x33], Vec::new(), 2);
+        assert!(lines[0].contains(" W=--------- ; "));
+        assert!(lines[1].contains(" W=0300-0302 ; "));
+    }
+
+    #[test]
+    fn test_full_stack() {
+        // CALL 0x200 sixteen times fills the stack
+        let lines = trace(&[0x22, 0x00], Vec::new(), 16);
+        assert!(lines[14].contains(" SP=0F "));
+        assert!(lines[15].contains(" SP=10 "));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::cpu::Cpu;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// A condition checked after each instruction. Each one fires when its
+/// condition becomes true, so execution can continue past it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// A byte in the range is written, e.g. by FX33 or FX55
+    MemoryWrite(RangeInclusive<usize>),
+    /// I points into the range
+    Index(RangeInclusive<usize>),
+    /// VX holds the value
+    Register { x: u8, value: u8 },
+    /// A sprite draw turns off a lit pixel
+    Collision,
+}
+
+/// Why stepping stopped, with the address of the instruction responsible
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    MemoryWrite { pc: usize, addr: usize },
+    Index { pc: usize, i: usize },
+    Register { pc: usize, x: u8, value: u8 },
+    Collision { pc: usize },
+}
+
+// The state watchpoints compare against, taken before each step
+pub(crate) struct Snapshot {
+    pc: usize,
+    i: usize,
+    v: [u8; 16],
+}
+
+impl Snapshot {
+    pub(crate) fn new(cpu: &Cpu) -> Self {
+        Self {
+            pc: cpu.get_pc(),
+            i: cpu.get_i(),
+            v: *cpu.get_v(),
+        }
+    }
+}
+
+impl Watchpoint {
+    pub(crate) fn check(&self, before: &Snapshot, cpu: &Cpu) -> Option<StopReason> {
+        let pc = before.pc;
+        match self {
+            Self::MemoryWrite(range) => {
+                let (first, last) = cpu.get_last_write()?;
+                let addr = first.max(*range.start());
+                (addr <= last.min(*range.end())).then_some(StopReason::MemoryWrite { pc, addr })
+            }
+            Self::Index(range) => {
+                let i = cpu.get_i();
+                (range.contains(&i) && !range.contains(&before.i))
+                    .then_some(StopReason::Index { pc, i })
+            }
+            &Self::Register { x, value } => {
+                let reached = cpu.get_v()[x as usize] == value && before.v[x as usize] != value;
+                reached.then_some(StopReason::Register { pc, x, value })
+            }
+            Self::Collision => cpu
+                .get_last_collision()
+                .then_some(StopReason::Collision { pc }),
+        }
+    }
+}
+
+// `write:ADDR[-ADDR]`, `i:ADDR[-ADDR]`, `vX=VALUE` or `collision`,
+// with numbers in decimal or 0x-prefixed hex
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "collision" {
+            return Ok(Self::Collision);
+        }
+        if let Some(text) = s.strip_prefix("write:") {
+            return parse_range(text).map(Self::MemoryWrite);
+        }
+        if let Some(text) = s.strip_prefix("i:") {
+            return parse_range(text).map(Self::Index);
+        }
+        if let Some((register, value)) = s.split_once('=')
+            && let Some(x) = register.strip_prefix(['v', 'V'])
+        {
+            let x = u8::from_str_radix(x, 16)
+                .ok()
+                .filter(|&x| x < 16 && register.len() == 2)
+                .ok_or_else(|| format!("Invalid register `{}`", register))?;
+            let value = u8::try_from(parse_number(value)?)
+                .map_err(|_| format!("Value `{}` does not fit in a byte", value))?;
+            return Ok(Self::Register { x, value });
+        }
+        Err(format!("Invalid watchpoint `{}`", s))
+    }
+}
+
+fn parse_number(text: &str) -> Result<usize, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid number `{}`", text))
+}
+
+/// Parses `ADDR` or `ADDR-ADDR`, in decimal or 0x-prefixed hex
+pub fn parse_range(text: &str) -> Result<RangeInclusive<usize>, String> {
+    let (start, end) = text.split_once('-').unwrap_or((text, text));
+    let (start, end) = (parse_number(start)?, parse_number(end)?);
+    if start > end {
+        return Err(format!("Empty range `{}`", text));
+    }
+    Ok(start..=end)
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MemoryWrite { pc, addr } => {
+                write!(f, "Write to 0x{:03X} at PC 0x{:03X}", addr, pc)
+            }
+            Self::Index { pc, i } => write!(f, "I set to 0x{:03X} at PC 0x{:03X}", i, pc),
+            Self::Register { pc, x, value } => {
+                write!(f, "V{:X} reached 0x{:02X} at PC 0x{:03X}", x, value, pc)
+            }
+            Self::Collision { pc } => write!(f, "Sprite collision at PC 0x{:03X}", pc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chip8, Platform, Quirks, StepOutcome};
+
+    fn run(program: &[u8], watchpoint: Watchpoint) -> Vec<StepOutcome> {
+        let mut chip8 = Chip8::new(program, Platform::Chip8, Quirks::default());
+        chip8.add_watchpoint(watchpoint);
+        (0..program.len() / 2)
+            .map(|_| chip8.step(0).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_memory_write() {
+        // LD I, 0x300; LD V0, 123; LD B, V0; LD [I], V0
+        let program = [0xA3, 0x00, 0x60, 0x7B, 0xF0, 0x33, 0xF0, 0x55];
+        let outcomes = run(&program, Watchpoint::MemoryWrite(0x302..=0x310));
+        assert_eq!(
+            outcomes[2],
+            StepOutcome::Stopped(StopReason::MemoryWrite {
+                pc: 0x204,
+                addr: 0x302
+            })
+        );
+        assert_eq!(outcomes[3], StepOutcome::Executed);
+    }
+
+    #[test]
+    fn test_index_and_register() {
+        // LD I, 0x300; LD I, 0x301; LD V3, 5; ADD V3, 1; LD V3, 6
+        let program = [0xA3, 0x00, 0xA3, 0x01, 0x63, 0x05, 0x73, 0x01, 0x63, 0x06];
+        let outcomes = run(&program, Watchpoint::Index(0x300..=0x3FF));
+        assert_eq!(
+            outcomes[0],
+            StepOutcome::Stopped(StopReason::Index {
+                pc: 0x200,
+                i: 0x300
+            })
+        );
+        assert_eq!(outcomes[1], StepOutcome::Executed);
+
+        let outcomes = run(&program, Watchpoint::Register { x: 3, value: 6 });
+        assert_eq!(
+            outcomes,
+            [
+                StepOutcome::Executed,
+                StepOutcome::Executed,
+                StepOutcome::Executed,
+                StepOutcome::Stopped(StopReason::Register {
+                    pc: 0x206,
+                    x: 3,
+                    value: 6
+                }),
+                StepOutcome::Executed,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collision() {
+        // LD F, V0; DRW V0, V0, 5; DRW V0, V0, 5
+        let program = [0xF0, 0x29, 0xD0, 0x05, 0xD0, 0x05];
+        let outcomes = run(&program, Watchpoint::Collision);
+        assert_eq!(outcomes[1], StepOutcome::Executed);
+        assert_eq!(
+            outcomes[2],
+            StepOutcome::Stopped(StopReason::Collision { pc: 0x204 })
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "write:0x300-0x30F".parse(),
+            Ok(Watchpoint::MemoryWrite(0x300..=0x30F))
+        );
+        assert_eq!("i:512".parse(), Ok(Watchpoint::Index(512..=512)));
+        assert_eq!(
+            "vA=0x10".parse(),
+            Ok(Watchpoint::Register { x: 10, value: 16 })
+        );
+        assert_eq!("collision".parse(), Ok(Watchpoint::Collision));
+        assert_eq!(
+            "v3=256".parse::<Watchpoint>(),
+            Err("Value `256` does not fit in a byte".to_string())
+        );
+        assert_eq!(
+            "vG=1".parse::<Watchpoint>(),
+            Err("Invalid register `vG`".to_string())
+        );
+        assert_eq!(
+            "write:0x30F-0x300".parse::<Watchpoint>(),
+            Err("Empty range `0x30F-0x300`".to_string())
+        );
+        assert_eq!(
+            "read:0x300".parse::<Watchpoint>(),
+            Err("Invalid watchpoint `read:0x300`".to_string())
+        );
+    }
+}
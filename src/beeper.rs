@@ -0,0 +1,185 @@
+use clap::ValueEnum;
+use std::f32::consts::TAU;
+
+pub const SAMPLE_RATE: u32 = 44100;
+// Length of the looped buffer, rounded to a whole number of periods
+const LOOP_SECONDS: f32 = 0.1;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    // `phase` is in the range [0, 1)
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (phase * TAU).sin(),
+        }
+    }
+}
+
+pub struct Beeper {
+    pub frequency: f32,
+    pub volume: f32,
+    pub waveform: Waveform,
+    phase: f32,
+}
+
+impl Beeper {
+    pub fn new(frequency: f32, volume: f32, waveform: Waveform) -> Self {
+        assert!(frequency > 0.0, "Frequency must be greater than 0");
+        Self {
+            frequency,
+            volume: volume.clamp(0.0, 1.0),
+            waveform,
+            phase: 0.0,
+        }
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn render(&mut self, buffer: &mut [f32]) {
+        let step = self.frequency / SAMPLE_RATE as f32;
+        for sample in buffer.iter_mut() {
+            *sample = self.waveform.sample(self.phase) * self.volume;
+            self.phase = (self.phase + step).fract();
+        }
+    }
+
+    // Renders a buffer that can be looped without a discontinuity
+    pub fn render_loop(&mut self) -> Vec<f32> {
+        let cycles = (self.frequency * LOOP_SECONDS).round().max(1.0);
+        let len = (cycles * SAMPLE_RATE as f32 / self.frequency).round() as usize;
+        let mut buffer = vec![0.0; len];
+        self.phase = 0.0;
+        self.render(&mut buffer);
+        buffer
+    }
+
+    // Renders one loop of an XO-CHIP 128-bit audio pattern
+    pub fn render_pattern(&self, pattern: &[u8; 16], pitch: u8) -> Vec<f32> {
+        let rate = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+        let bits = pattern.len() * 8;
+        let len = (bits as f32 / rate * SAMPLE_RATE as f32).round().max(1.0) as usize;
+        (0..len)
+            .map(|i| {
+                let bit = (i as f32 * rate / SAMPLE_RATE as f32) as usize % bits;
+                let on = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                if on { self.volume } else { -self.volume }
+            })
+            .collect()
+    }
+}
+
+// Encodes samples as a 16-bit mono PCM WAV file
+pub fn encode_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        wav.extend_from_slice(&value.to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_square() {
+        let mut beeper = Beeper::new(SAMPLE_RATE as f32 / 4.0, 0.5, Waveform::Square);
+        let mut buffer = [0.0; 8];
+        beeper.render(&mut buffer);
+        assert_eq!(buffer, [0.5, 0.5, -0.5, -0.5, 0.5, 0.5, -0.5, -0.5]);
+    }
+
+    #[test]
+    fn test_render_continues_phase() {
+        let mut beeper = Beeper::new(SAMPLE_RATE as f32 / 4.0, 1.0, Waveform::Sawtooth);
+        let mut first = [0.0; 2];
+        let mut second = [0.0; 2];
+        beeper.render(&mut first);
+        beeper.render(&mut second);
+        assert_eq!(first, [-1.0, -0.5]);
+        assert_eq!(second, [0.0, 0.5]);
+    }
+
+    #[test]
+    fn test_render_triangle() {
+        let mut beeper = Beeper::new(SAMPLE_RATE as f32 / 4.0, 1.0, Waveform::Triangle);
+        let mut buffer = [0.0; 4];
+        beeper.render(&mut buffer);
+        assert_eq!(buffer, [-1.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_volume_is_clamped() {
+        let mut beeper = Beeper::new(440.0, 2.0, Waveform::Square);
+        assert_eq!(beeper.volume, 1.0);
+        beeper.set_volume(-1.0);
+        assert_eq!(beeper.volume, 0.0);
+    }
+
+    #[test]
+    fn test_render_loop_is_whole_periods() {
+        let mut beeper = Beeper::new(441.0, 1.0, Waveform::Square);
+        let buffer = beeper.render_loop();
+        assert_eq!(buffer.len(), 4400);
+        assert_eq!(buffer[0], 1.0);
+        assert_eq!(buffer[buffer.len() - 1], -1.0);
+    }
+
+    #[test]
+    fn test_render_pattern() {
+        let beeper = Beeper::new(440.0, 0.5, Waveform::Square);
+        let mut pattern = [0; 16];
+        pattern[0] = 0xFF;
+        let buffer = beeper.render_pattern(&pattern, 64);
+        assert_eq!(buffer.len(), 1411);
+        assert_eq!(buffer[0], 0.5);
+        assert_eq!(buffer[87], 0.5);
+        assert_eq!(buffer[100], -0.5);
+        let faster = beeper.render_pattern(&pattern, 112);
+        assert_eq!(faster.len(), 706);
+    }
+
+    #[test]
+    fn test_encode_wav() {
+        let wav = encode_wav(&[0.0, 1.0, -1.0], 8000);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 42);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 8000);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 6);
+        assert_eq!(&wav[44..], &[0, 0, 0xFF, 0x7F, 0x01, 0x80]);
+    }
+}
@@ -0,0 +1,322 @@
+use core::{Cpu, Instruction, KeyWait, PROGRAM_START};
+use std::collections::BTreeSet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    // Stop before the next instruction
+    Step,
+    // Stop at `pc` once the stack is back to at most `sp` entries
+    Return { pc: usize, sp: u8 },
+    // Stop once the stack has fewer than this many entries
+    Unwound(u8),
+    Address(usize),
+}
+
+pub struct Debugger {
+    enabled: bool,
+    paused: bool,
+    breakpoints: BTreeSet<usize>,
+    target: Option<Target>,
+    // Lets the instruction under a breakpoint run when execution continues from it
+    resuming: bool,
+    cursor: usize,
+}
+
+impl Debugger {
+    // When enabled the program starts paused on its first instruction
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            paused: enabled,
+            breakpoints: BTreeSet::new(),
+            target: None,
+            resuming: false,
+            cursor: PROGRAM_START,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn move_cursor(&mut self, offset: isize) {
+        self.cursor = self.cursor.saturating_add_signed(offset);
+    }
+
+    pub fn has_breakpoint(&self, addr: usize) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    pub fn set_breakpoint(&mut self, addr: usize, enabled: bool) {
+        if enabled {
+            self.breakpoints.insert(addr);
+        } else {
+            self.breakpoints.remove(&addr);
+        }
+    }
+
+    pub fn toggle_breakpoint(&mut self, addr: usize) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    pub fn pause(&mut self, pc: usize) {
+        self.paused = true;
+        self.target = None;
+        self.cursor = pc;
+    }
+
+    pub fn resume(&mut self) {
+        self.run(None);
+    }
+
+    pub fn step(&mut self) {
+        self.run(Some(Target::Step));
+    }
+
+    // Runs a CALL through to its return, and steps anything else
+    pub fn step_over(&mut self, cpu: &Cpu, memory: &[u8]) {
+        match instruction_at(memory, cpu.get_pc()) {
+            Some(Instruction::Call(_)) => self.run(Some(Target::Return {
+                pc: cpu.get_pc() + 2,
+                sp: cpu.get_sp(),
+            })),
+            _ => self.step(),
+        }
+    }
+
+    // Does nothing outside a subroutine
+    pub fn step_out(&mut self, cpu: &Cpu) {
+        if cpu.get_sp() > 0 {
+            self.run(Some(Target::Unwound(cpu.get_sp())));
+        }
+    }
+
+    pub fn run_to_cursor(&mut self) {
+        self.run(Some(Target::Address(self.cursor)));
+    }
+
+    fn run(&mut self, target: Option<Target>) {
+        self.paused = false;
+        self.resuming = true;
+        self.target = target;
+    }
+
+    // Called before each instruction; pauses and returns true if it should not run
+    pub fn should_break(&mut self, cpu: &Cpu) -> bool {
+        if self.paused {
+            return true;
+        }
+        let first = std::mem::take(&mut self.resuming);
+        let (pc, sp) = (cpu.get_pc(), cpu.get_sp());
+        let reached = match self.target {
+            Some(Target::Step) => !first,
+            Some(Target::Return {
+                pc: addr,
+                sp: depth,
+            }) => pc == addr && sp <= depth,
+            Some(Target::Unwound(depth)) => sp < depth,
+            Some(Target::Address(addr)) => pc == addr && !first,
+            None => false,
+        };
+        if reached || (!first && self.breakpoints.contains(&pc)) {
+            self.pause(pc);
+        }
+        self.paused
+    }
+}
+
+pub fn instruction_at(memory: &[u8], addr: usize) -> Option<Instruction> {
+    let word = |addr: usize| {
+        Some(u16::from_be_bytes([
+            *memory.get(addr)?,
+            *memory.get(addr + 1)?,
+        ]))
+    };
+    let opcode = word(addr)?;
+    let operand = if Instruction::is_long(opcode) {
+        word(addr + 2)?
+    } else {
+        0
+    };
+    Instruction::decode_long(opcode, operand)
+}
+
+// One row per word from `before` words ahead of `center` to `after` words past it.
+// Rows are not aligned to instructions, so the operand of a long load gets its own row.
+pub fn listing(memory: &[u8], center: usize, before: usize, after: usize) -> Vec<(usize, String)> {
+    let start = center.saturating_sub(before * 2);
+    let end = (center + after * 2).min(memory.len().saturating_sub(2));
+    (start..=end)
+        .step_by(2)
+        .map(|addr| {
+            let word = u16::from_be_bytes([memory[addr], memory[addr + 1]]);
+            let text = match instruction_at(memory, addr) {
+                Some(instruction) => instruction.to_string(),
+                None => "???".to_string(),
+            };
+            (addr, format!("{:04X}  {:04X}  {}", addr, word, text))
+        })
+        .collect()
+}
+
+pub fn registers_text(cpu: &Cpu) -> Vec<String> {
+    let mut lines = vec![
+        format!(
+            "PC {:04X}  I {:04X}  SP {:X}",
+            cpu.get_pc(),
+            cpu.get_i(),
+            cpu.get_sp()
+        ),
+        format!(
+            "DT {:02X}  ST {:02X}",
+            cpu.get_delay_timer(),
+            cpu.get_sound_timer()
+        ),
+    ];
+    for (row, values) in cpu.get_v().chunks(4).enumerate() {
+        let cells: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("V{:X} {:02X}", row * 4 + i, value))
+            .collect();
+        lines.push(cells.join("  "));
+    }
+    let stack: Vec<String> = cpu
+        .get_stack()
+        .iter()
+        .map(|addr| format!("{:04X}", addr))
+        .collect();
+    lines.push(format!("Stack {}", stack.join(" ")));
+    lines
+}
+
+// What a pending FX0A is waiting for, if there is one
+pub fn key_wait_text(cpu: &Cpu) -> Option<String> {
+    cpu.get_key_wait().map(|wait| match wait {
+        KeyWait::Press => "Waiting for key".to_string(),
+        KeyWait::Release(key) => format!("Waiting for release of {:X}", key),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{Chip8, Platform, Quirks};
+
+    // 200: CALL 206, 202: LD V1, 2, 204: JP 204, 206: LD V0, 1, 208: RET
+    const PROGRAM: [u8; 10] = [0x22, 0x06, 0x61, 0x02, 0x12, 0x04, 0x60, 0x01, 0x00, 0xEE];
+
+    // Steps until the debugger stops, as the frontend does each frame
+    fn run(debugger: &mut Debugger, chip8: &mut Chip8) -> usize {
+        let mut count = 0;
+        while count < 100 && !debugger.should_break(&chip8.cpu) {
+            chip8.step(0).unwrap();
+            count += 1;
+        }
+        count
+    }
+
+    fn setup() -> (Debugger, Chip8) {
+        let chip8 = Chip8::new(&PROGRAM, Platform::Chip8, Quirks::default());
+        (Debugger::new(true), chip8)
+    }
+
+    #[test]
+    fn test_step() {
+        let (mut debugger, mut chip8) = setup();
+        assert_eq!(run(&mut debugger, &mut chip8), 0);
+        debugger.step();
+        assert_eq!(run(&mut debugger, &mut chip8), 1);
+        assert_eq!(chip8.cpu.get_pc(), 0x206);
+        assert_eq!(debugger.cursor(), 0x206);
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let (mut debugger, mut chip8) = setup();
+        debugger.step_over(&chip8.cpu, chip8.get_memory());
+        assert_eq!(run(&mut debugger, &mut chip8), 3);
+        assert_eq!(chip8.cpu.get_pc(), 0x202);
+        assert_eq!(chip8.cpu.get_v()[0], 1);
+
+        let (mut debugger, mut chip8) = setup();
+        debugger.step_out(&chip8.cpu);
+        assert!(debugger.is_paused());
+        debugger.step();
+        run(&mut debugger, &mut chip8);
+        debugger.step_out(&chip8.cpu);
+        assert_eq!(run(&mut debugger, &mut chip8), 2);
+        assert_eq!(chip8.cpu.get_pc(), 0x202);
+    }
+
+    #[test]
+    fn test_breakpoints_and_run_to_cursor() {
+        let (mut debugger, mut chip8) = setup();
+        debugger.toggle_breakpoint(0x208);
+        debugger.resume();
+        assert_eq!(run(&mut debugger, &mut chip8), 2);
+        assert_eq!(chip8.cpu.get_pc(), 0x208);
+        // Resuming runs the instruction under the breakpoint, then never returns to it
+        debugger.resume();
+        assert_eq!(run(&mut debugger, &mut chip8), 100);
+
+        let (mut debugger, mut chip8) = setup();
+        debugger.move_cursor(4);
+        debugger.run_to_cursor();
+        assert_eq!(run(&mut debugger, &mut chip8), 4);
+        assert_eq!(chip8.cpu.get_pc(), 0x204);
+        debugger.toggle_breakpoint(0x204);
+        debugger.toggle_breakpoint(0x204);
+        assert!(!debugger.has_breakpoint(0x204));
+    }
+
+    #[test]
+    fn test_listing() {
+        let (_, chip8) = setup();
+        let rows = listing(chip8.get_memory(), 0x202, 1, 2);
+        let addrs: Vec<usize> = rows.iter().map(|&(addr, _)| addr).collect();
+        assert_eq!(addrs, [0x200, 0x202, 0x204, 0x206]);
+        assert_eq!(rows[1].1, "0202  6102  LD V1, 0x02");
+        assert_eq!(rows[0].1, "0200  2206  CALL 0x206");
+    }
+
+    #[test]
+    fn test_registers_text() {
+        let (mut debugger, mut chip8) = setup();
+        debugger.step();
+        run(&mut debugger, &mut chip8);
+        let lines = registers_text(&chip8.cpu);
+        assert_eq!(lines[0], "PC 0206  I 0000  SP 1");
+        assert_eq!(lines[2], "V0 00  V1 00  V2 00  V3 00");
+        assert_eq!(lines[6], "Stack 0202");
+    }
+
+    #[test]
+    fn test_key_wait_text() {
+        let quirks = Quirks {
+            key_release: true,
+            ..Quirks::default()
+        };
+        let mut chip8 = Chip8::new(&[0xF0, 0x0A], Platform::Chip8, quirks);
+        assert_eq!(key_wait_text(&chip8.cpu), None);
+        chip8.step(0).unwrap();
+        assert_eq!(key_wait_text(&chip8.cpu).unwrap(), "Waiting for key");
+        chip8.step(1 << 0xB).unwrap();
+        assert_eq!(
+            key_wait_text(&chip8.cpu).unwrap(),
+            "Waiting for release of B"
+        );
+        chip8.step(0).unwrap();
+        assert_eq!(key_wait_text(&chip8.cpu), None);
+    }
+}
@@ -0,0 +1,67 @@
+use clap::ValueEnum;
+use core::{Disassembly, Line};
+use serde_json::{Value, json};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Format {
+    Text,
+    Octo,
+    Json,
+}
+
+pub fn render(disassembly: &Disassembly, format: Format) -> String {
+    match format {
+        Format::Text => disassembly.to_text(),
+        Format::Octo => disassembly.to_octo(),
+        Format::Json => serde_json::to_string_pretty(&to_json(disassembly)).unwrap() + "\n",
+    }
+}
+
+fn to_json(disassembly: &Disassembly) -> Value {
+    let lines: Vec<Value> = disassembly
+        .lines
+        .iter()
+        .map(|line| {
+            let label = disassembly.labels.get(&line.addr());
+            match line {
+                Line::Code {
+                    addr,
+                    instruction,
+                    indirect,
+                } => json!({
+                    "address": addr,
+                    "label": label,
+                    "kind": "code",
+                    "bytes": instruction.to_bytes(),
+                    "mnemonic": disassembly.mnemonic(instruction),
+                    "indirect": indirect,
+                }),
+                Line::Data { addr, bytes } => json!({
+                    "address": addr,
+                    "label": label,
+                    "kind": "data",
+                    "bytes": bytes,
+                }),
+            }
+        })
+        .collect();
+    json!({ "lines": lines })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::disassemble;
+
+    #[test]
+    fn test_to_json() {
+        let value = to_json(&disassemble(&[0x22, 0x04, 0x12, 0x02, 0x00, 0xEE, 0xAB]));
+        let lines = value["lines"].as_array().unwrap();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["mnemonic"], "CALL sub_204");
+        assert_eq!(lines[1]["label"], "loc_202");
+        assert_eq!(lines[2]["bytes"], json!([0x00, 0xEE]));
+        assert_eq!(lines[3]["kind"], "data");
+        assert!(lines[3]["label"].is_null());
+    }
+}
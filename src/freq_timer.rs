@@ -12,6 +12,11 @@ impl FrequencyTimer {
         }
     }
 
+    pub fn set_frequency(&mut self, hz: u32) {
+        assert!(hz > 0, "Frequency must be greater than 0");
+        self.frame_time = 1000.0 / (hz as f32);
+    }
+
     pub fn update(&mut self, elapsed_ms: f32) -> usize {
         let mut count = 0;
         self.elapsed_ms += elapsed_ms;
@@ -35,4 +40,12 @@ mod tests {
         assert_eq!(timer.update(10.0), 0);
         assert_eq!(timer.update(7.0), 1);
     }
+
+    #[test]
+    fn test_set_frequency() {
+        let mut timer = FrequencyTimer::new(60);
+        timer.set_frequency(1000);
+        assert_eq!(timer.update(10.5), 10);
+        assert_eq!(timer.update(0.5), 1);
+    }
 }
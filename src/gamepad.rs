@@ -0,0 +1,274 @@
+use crate::keymap;
+use anyhow::{Result, anyhow};
+use ggez::event::{Axis, Button};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use toml::Value;
+
+// How far a stick has to move before it counts as pressed
+const DEADZONE: f32 = 0.5;
+
+// A button, or a stick pushed one way. D-pads that report axes are turned
+// into their buttons, so one binding covers both kinds of controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Input {
+    Button(Button),
+    Stick(Axis, bool),
+}
+
+// Inputs that can be bound, by the names used in key map files
+const INPUT_NAMES: [(&str, Input); 26] = [
+    ("South", Input::Button(Button::South)),
+    ("East", Input::Button(Button::East)),
+    ("North", Input::Button(Button::North)),
+    ("West", Input::Button(Button::West)),
+    ("C", Input::Button(Button::C)),
+    ("Z", Input::Button(Button::Z)),
+    ("LeftTrigger", Input::Button(Button::LeftTrigger)),
+    ("LeftTrigger2", Input::Button(Button::LeftTrigger2)),
+    ("RightTrigger", Input::Button(Button::RightTrigger)),
+    ("RightTrigger2", Input::Button(Button::RightTrigger2)),
+    ("Select", Input::Button(Button::Select)),
+    ("Start", Input::Button(Button::Start)),
+    ("LeftThumb", Input::Button(Button::LeftThumb)),
+    ("RightThumb", Input::Button(Button::RightThumb)),
+    ("DPadUp", Input::Button(Button::DPadUp)),
+    ("DPadDown", Input::Button(Button::DPadDown)),
+    ("DPadLeft", Input::Button(Button::DPadLeft)),
+    ("DPadRight", Input::Button(Button::DPadRight)),
+    ("LeftStickUp", Input::Stick(Axis::LeftStickY, true)),
+    ("LeftStickDown", Input::Stick(Axis::LeftStickY, false)),
+    ("LeftStickLeft", Input::Stick(Axis::LeftStickX, false)),
+    ("LeftStickRight", Input::Stick(Axis::LeftStickX, true)),
+    ("RightStickUp", Input::Stick(Axis::RightStickY, true)),
+    ("RightStickDown", Input::Stick(Axis::RightStickY, false)),
+    ("RightStickLeft", Input::Stick(Axis::RightStickX, false)),
+    ("RightStickRight", Input::Stick(Axis::RightStickX, true)),
+];
+
+pub fn input_name(input: Input) -> Option<&'static str> {
+    INPUT_NAMES
+        .iter()
+        .find(|&&(_, i)| i == input)
+        .map(|&(name, _)| name)
+}
+
+fn parse_input(name: &str) -> Result<Input> {
+    INPUT_NAMES
+        .iter()
+        .find(|&&(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, i)| i)
+        .ok_or_else(|| anyhow!("unknown gamepad input `{}`", name))
+}
+
+// The inputs bound to each CHIP-8 key, indexed by its value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GamepadMap {
+    keys: [Vec<Input>; 16],
+}
+
+// Directions go to 2/4/6/8, the face buttons to 5, A, B and 0
+impl Default for GamepadMap {
+    fn default() -> Self {
+        let mut keys: [Vec<Input>; 16] = Default::default();
+        let bind = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| parse_input(name).unwrap())
+                .collect()
+        };
+        keys[0x2] = bind(&["DPadUp", "LeftStickUp"]);
+        keys[0x4] = bind(&["DPadLeft", "LeftStickLeft"]);
+        keys[0x6] = bind(&["DPadRight", "LeftStickRight"]);
+        keys[0x8] = bind(&["DPadDown", "LeftStickDown"]);
+        keys[0x5] = bind(&["South"]);
+        keys[0xA] = bind(&["East"]);
+        keys[0xB] = bind(&["North"]);
+        keys[0x0] = bind(&["West"]);
+        keys[0xF] = bind(&["Start"]);
+        keys[0xE] = bind(&["Select"]);
+        Self { keys }
+    }
+}
+
+impl GamepadMap {
+    // Replaces the bindings of the keys in a `[gamepad]` table, which has the
+    // same form as `[keys]`
+    pub fn apply(&mut self, table: &Value) -> Result<()> {
+        for (key, bindings) in keymap::parse_bindings("gamepad", table, parse_input)? {
+            self.keys[key] = bindings;
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> String {
+        keymap::format_bindings("gamepad", &self.keys, input_name)
+    }
+
+    pub fn mask(&self, active: &HashSet<Input>) -> u16 {
+        self.keys.iter().enumerate().fold(0, |acc, (i, bindings)| {
+            let pressed = bindings.iter().any(|input| active.contains(input));
+            acc | if pressed { 1 << i } else { 0 }
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change<Id> {
+    Connected(Id),
+    Disconnected(Id),
+}
+
+// The inputs held on each connected pad, fed from ggez's gamepad events
+pub struct Gamepads<Id> {
+    active: HashMap<Id, HashSet<Input>>,
+}
+
+impl<Id: Copy + Eq + Hash> Gamepads<Id> {
+    pub fn new() -> Self {
+        Self {
+            active: HashMap::new(),
+        }
+    }
+
+    pub fn button(&mut self, id: Id, button: Button, pressed: bool) {
+        let active = self.active.entry(id).or_default();
+        if pressed {
+            active.insert(Input::Button(button));
+        } else {
+            active.remove(&Input::Button(button));
+        }
+    }
+
+    pub fn axis(&mut self, id: Id, axis: Axis, value: f32) {
+        let active = self.active.entry(id).or_default();
+        let (negative, positive) = match axis {
+            Axis::DPadX => (
+                Input::Button(Button::DPadLeft),
+                Input::Button(Button::DPadRight),
+            ),
+            Axis::DPadY => (
+                Input::Button(Button::DPadDown),
+                Input::Button(Button::DPadUp),
+            ),
+            _ => (Input::Stick(axis, false), Input::Stick(axis, true)),
+        };
+        for (input, held) in [(negative, value < -DEADZONE), (positive, value > DEADZONE)] {
+            if held {
+                active.insert(input);
+            } else {
+                active.remove(&input);
+            }
+        }
+    }
+
+    // Brings the pads in line with those connected now, releasing everything a
+    // pad held when it is unplugged
+    pub fn sync(&mut self, connected: &[Id]) -> Vec<Change<Id>> {
+        let mut changes: Vec<Change<Id>> = connected
+            .iter()
+            .filter(|id| !self.active.contains_key(id))
+            .map(|&id| Change::Connected(id))
+            .collect();
+        for change in &changes {
+            if let Change::Connected(id) = change {
+                self.active.insert(*id, HashSet::new());
+            }
+        }
+        let gone: Vec<Id> = self
+            .active
+            .keys()
+            .filter(|id| !connected.contains(id))
+            .copied()
+            .collect();
+        for id in gone {
+            self.active.remove(&id);
+            changes.push(Change::Disconnected(id));
+        }
+        changes
+    }
+
+    pub fn mask(&self, map: &GamepadMap) -> u16 {
+        self.active
+            .values()
+            .fold(0, |acc, active| acc | map.mask(active))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_map() {
+        let map = GamepadMap::default();
+        let mut pads = Gamepads::new();
+        pads.button(1, Button::DPadUp, true);
+        pads.button(1, Button::South, true);
+        assert_eq!(pads.mask(&map), 0x0024);
+        pads.button(1, Button::DPadUp, false);
+        assert_eq!(pads.mask(&map), 0x0020);
+        // A second pad adds its keys
+        pads.button(2, Button::Start, true);
+        assert_eq!(pads.mask(&map), 0x8020);
+    }
+
+    #[test]
+    fn test_axes() {
+        let map = GamepadMap::default();
+        let mut pads = Gamepads::new();
+        pads.axis(0, Axis::LeftStickX, -0.9);
+        assert_eq!(pads.mask(&map), 1 << 4);
+        pads.axis(0, Axis::LeftStickX, 0.2);
+        assert_eq!(pads.mask(&map), 0);
+        pads.axis(0, Axis::LeftStickY, 1.0);
+        assert_eq!(pads.mask(&map), 1 << 2);
+        pads.axis(0, Axis::LeftStickY, 0.0);
+        // D-pads reported as axes act as the buttons
+        pads.axis(0, Axis::DPadX, 1.0);
+        assert_eq!(pads.mask(&map), 1 << 6);
+        pads.axis(0, Axis::DPadX, 0.0);
+        assert_eq!(pads.mask(&map), 0);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut map = GamepadMap::default();
+        let value: Value = "5 = [\"RightTrigger\", \"rightstickleft\"]\n2 = \"North\"\n"
+            .parse()
+            .unwrap();
+        map.apply(&value).unwrap();
+        let mut pads = Gamepads::new();
+        pads.axis(0, Axis::RightStickX, -1.0);
+        assert_eq!(pads.mask(&map), 1 << 5);
+        pads.button(0, Button::South, true);
+        pads.button(0, Button::DPadUp, true);
+        assert_eq!(pads.mask(&map), 1 << 5);
+        pads.button(0, Button::North, true);
+        assert_eq!(pads.mask(&map), (1 << 5) | (1 << 2) | (1 << 0xB));
+
+        let table: Value = map.to_toml().parse().unwrap();
+        let mut parsed = GamepadMap::default();
+        parsed.apply(&table["gamepad"]).unwrap();
+        assert_eq!(parsed, map);
+
+        let value: Value = "3 = \"Turbo\"\n".parse().unwrap();
+        assert_eq!(
+            map.apply(&value).unwrap_err().to_string(),
+            "unknown gamepad input `Turbo`"
+        );
+    }
+
+    #[test]
+    fn test_hot_plug() {
+        let map = GamepadMap::default();
+        let mut pads = Gamepads::new();
+        assert_eq!(pads.sync(&[7]), [Change::Connected(7)]);
+        assert_eq!(pads.sync(&[7]), []);
+        pads.button(7, Button::South, true);
+        assert_eq!(pads.mask(&map), 1 << 5);
+        // Unplugging with a button held must not leave the key stuck
+        assert_eq!(pads.sync(&[]), [Change::Disconnected(7)]);
+        assert_eq!(pads.mask(&map), 0);
+    }
+}
@@ -0,0 +1,560 @@
+use crate::debugger::Debugger;
+use core::Chip8;
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const INTERRUPT: u8 = 0x03;
+const PACKET_SIZE: usize = 0x1000;
+// V0-VF, then I, PC, SP, DT and ST
+const REGISTER_COUNT: usize = 21;
+
+// Serves one GDB client at a time over the remote serial protocol. The socket
+// is polled from the frame loop, and execution goes through the debugger, so
+// breakpoints set from GDB show up in the debugger panel and vice versa.
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+struct Client {
+    stream: TcpStream,
+    input: Vec<u8>,
+    ack: bool,
+    // GDB is waiting for a stop reply
+    running: bool,
+    interrupted: bool,
+}
+
+impl GdbStub {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Handles whatever the client has sent, and reports the debugger pausing
+    pub fn poll(&mut self, chip8: &mut Chip8, debugger: &mut Debugger) {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, addr)) => match Client::new(stream) {
+                    Ok(client) => {
+                        println!("GDB connected from {}", addr);
+                        debugger.pause(chip8.cpu.get_pc());
+                        self.client = Some(client);
+                    }
+                    Err(e) => eprintln!("Failed to set up GDB connection: {}", e),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => eprintln!("Failed to accept GDB connection: {}", e),
+            }
+        }
+        let Some(client) = &mut self.client else {
+            return;
+        };
+        if let Err(e) = client.poll(chip8, debugger) {
+            if e.kind() != ErrorKind::UnexpectedEof {
+                eprintln!("GDB connection lost: {}", e);
+            }
+            println!("GDB disconnected");
+            self.client = None;
+        }
+    }
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            input: Vec::new(),
+            ack: true,
+            running: false,
+            interrupted: false,
+        })
+    }
+
+    fn poll(&mut self, chip8: &mut Chip8, debugger: &mut Debugger) -> io::Result<()> {
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(len) => self.input.extend_from_slice(&buffer[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        while let Some(packet) = self.next_packet()? {
+            match packet {
+                Packet::Interrupt => {
+                    self.interrupted = true;
+                    debugger.pause(chip8.cpu.get_pc());
+                }
+                Packet::Command(command) => {
+                    if let Some(reply) = self.handle(&command, chip8, debugger) {
+                        self.send(&reply)?;
+                    }
+                    if command == "D" || command == "k" {
+                        return Err(ErrorKind::UnexpectedEof.into());
+                    }
+                }
+            }
+        }
+        if self.running && debugger.is_paused() {
+            self.running = false;
+            let signal = if std::mem::take(&mut self.interrupted) {
+                SIGINT
+            } else {
+                SIGTRAP
+            };
+            self.send(&format!("S{:02x}", signal))?;
+        }
+        Ok(())
+    }
+
+    fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            match self.input.first() {
+                None => return Ok(None),
+                Some(&INTERRUPT) => {
+                    self.input.remove(0);
+                    return Ok(Some(Packet::Interrupt));
+                }
+                Some(b'$') => break,
+                // Acknowledgements, and anything else between packets
+                Some(_) => {
+                    self.input.remove(0);
+                }
+            }
+        }
+        let Some(end) = self.input.iter().position(|&b| b == b'#') else {
+            return Ok(None);
+        };
+        if self.input.len() < end + 3 {
+            return Ok(None);
+        }
+        let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+        let body = &packet[1..end];
+        let checksum = std::str::from_utf8(&packet[end + 1..])
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if self.ack {
+            let valid = checksum == Some(checksum_of(body));
+            self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            if !valid {
+                return self.next_packet();
+            }
+        }
+        Ok(Some(Packet::Command(
+            String::from_utf8_lossy(body).into_owned(),
+        )))
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+
+    // Returns the reply, or None when there is none yet, as for continue and step
+    fn handle(
+        &mut self,
+        command: &str,
+        chip8: &mut Chip8,
+        debugger: &mut Debugger,
+    ) -> Option<String> {
+        let (kind, args) = command.split_at(command.len().min(1));
+        match kind {
+            "c" | "s" => {
+                if !args.is_empty() {
+                    let Ok(pc) = usize::from_str_radix(args, 16) else {
+                        return Some("E01".to_string());
+                    };
+                    chip8.cpu.set_pc(pc);
+                }
+                if kind == "c" {
+                    debugger.resume();
+                } else {
+                    debugger.step();
+                }
+                self.running = true;
+                None
+            }
+            "k" => None,
+            "D" => {
+                debugger.resume();
+                Some("OK".to_string())
+            }
+            "q" | "Q" => Some(self.query(command)),
+            _ => Some(respond(kind, args, chip8, debugger).unwrap_or_else(|| "E01".to_string())),
+        }
+    }
+
+    fn query(&mut self, command: &str) -> String {
+        if command.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+",
+                PACKET_SIZE
+            );
+        }
+        if command == "QStartNoAckMode" {
+            self.ack = false;
+            return "OK".to_string();
+        }
+        if let Some(args) = command.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, len)) = parse_pair(args) else {
+                return "E01".to_string();
+            };
+            let xml = target_xml();
+            let start = offset.min(xml.len());
+            let end = start.saturating_add(len).min(xml.len());
+            let more = if end < xml.len() { 'm' } else { 'l' };
+            return format!("{}{}", more, &xml[start..end]);
+        }
+        match command {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+// Replies to requests that complete immediately, or None if they are malformed
+fn respond(kind: &str, args: &str, chip8: &mut Chip8, debugger: &mut Debugger) -> Option<String> {
+    let reply = match kind {
+        "?" => format!("S{:02x}", SIGTRAP),
+        "g" => (0..REGISTER_COUNT)
+            .map(|n| read_register(chip8, n))
+            .collect(),
+        "G" => {
+            let mut rest = args;
+            for n in 0..REGISTER_COUNT {
+                let (value, tail) = rest.split_at_checked(register_size(n) * 2)?;
+                if !write_register(chip8, n, value) {
+                    return None;
+                }
+                rest = tail;
+            }
+            "OK".to_string()
+        }
+        "p" => {
+            let n = usize::from_str_radix(args, 16).ok()?;
+            (n < REGISTER_COUNT).then(|| read_register(chip8, n))?
+        }
+        "P" => {
+            let (n, value) = args.split_once('=')?;
+            let n = usize::from_str_radix(n, 16).ok()?;
+            if n >= REGISTER_COUNT || !write_register(chip8, n, value) {
+                return None;
+            }
+            "OK".to_string()
+        }
+        "m" => {
+            let (addr, len) = parse_pair(args)?;
+            to_hex(chip8.get_memory().get(addr..addr.checked_add(len)?)?)
+        }
+        "M" => {
+            let (range, data) = args.split_once(':')?;
+            let (addr, len) = parse_pair(range)?;
+            let bytes = from_hex(data).filter(|bytes| bytes.len() == len)?;
+            let target = chip8.memory.data.get_mut(addr..addr.checked_add(len)?)?;
+            target.copy_from_slice(&bytes);
+            "OK".to_string()
+        }
+        "Z" | "z" => {
+            let mut fields = args.split(',');
+            if fields.next() != Some("0") {
+                // Only software breakpoints are supported
+                return Some(String::new());
+            }
+            let addr = usize::from_str_radix(fields.next()?, 16).ok()?;
+            debugger.set_breakpoint(addr, kind == "Z");
+            "OK".to_string()
+        }
+        "H" | "T" => "OK".to_string(),
+        _ => String::new(),
+    };
+    Some(reply)
+}
+
+enum Packet {
+    Interrupt,
+    Command(String),
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &b| sum.wrapping_add(b))
+}
+
+fn parse_pair(args: &str) -> Option<(usize, usize)> {
+    let (a, b) = args.split_once(',')?;
+    Some((
+        usize::from_str_radix(a, 16).ok()?,
+        usize::from_str_radix(b, 16).ok()?,
+    ))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, b| {
+        let _ = write!(out, "{:02x}", b);
+        out
+    })
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn register_size(n: usize) -> usize {
+    match n {
+        16 | 17 => 2,
+        _ => 1,
+    }
+}
+
+// Wide registers are sent little-endian
+fn read_register(chip8: &Chip8, n: usize) -> String {
+    let cpu = &chip8.cpu;
+    match n {
+        0..16 => format!("{:02x}", cpu.get_v()[n]),
+        16 => to_hex(&(cpu.get_i() as u16).to_le_bytes()),
+        17 => to_hex(&(cpu.get_pc() as u16).to_le_bytes()),
+        18 => format!("{:02x}", cpu.get_sp()),
+        19 => format!("{:02x}", cpu.get_delay_timer()),
+        _ => format!("{:02x}", cpu.get_sound_timer()),
+    }
+}
+
+// Returns false if the value is malformed or out of range
+fn write_register(chip8: &mut Chip8, n: usize, hex: &str) -> bool {
+    let Some(bytes) = from_hex(hex).filter(|bytes| bytes.len() == register_size(n)) else {
+        return false;
+    };
+    let cpu = &mut chip8.cpu;
+    let wide = || u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+    match n {
+        0..16 => cpu.set_v(n, bytes[0]),
+        16 => cpu.set_i(wide()),
+        17 => cpu.set_pc(wide()),
+        18 if bytes[0] <= 16 => cpu.set_sp(bytes[0]),
+        18 => return false,
+        19 => cpu.set_delay_timer(bytes[0]),
+        _ => cpu.set_sound_timer(bytes[0]),
+    }
+    true
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n<feature name=\"org.chip8.core\">\n",
+    );
+    for x in 0..16 {
+        let _ = writeln!(
+            xml,
+            "<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>",
+            x, x
+        );
+    }
+    xml.push_str(
+        "<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\n\
+         <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\n\
+         <reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>\n\
+         <reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>\n\
+         <reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>\n\
+         </feature>\n</target>\n",
+    );
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{Platform, Quirks};
+    use std::time::{Duration, Instant};
+
+    // 200: LD V0, 5; 202: ADD V0, 1; 204: JP 202
+    const PROGRAM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+    struct Harness {
+        stub: GdbStub,
+        client: TcpStream,
+        chip8: Chip8,
+        debugger: Debugger,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let mut stub = GdbStub::bind(0).unwrap();
+            let client = TcpStream::connect(stub.local_addr().unwrap()).unwrap();
+            client.set_nonblocking(true).unwrap();
+            let mut chip8 = Chip8::new(&PROGRAM, Platform::Chip8, Quirks::default());
+            let mut debugger = Debugger::new(false);
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while stub.client.is_none() && Instant::now() < deadline {
+                stub.poll(&mut chip8, &mut debugger);
+            }
+            assert!(debugger.is_paused());
+            Self {
+                stub,
+                client,
+                chip8,
+                debugger,
+            }
+        }
+
+        fn send(&mut self, data: &[u8]) {
+            self.client.write_all(data).unwrap();
+        }
+
+        // Polls the stub until a whole packet comes back, skipping acks
+        fn reply(&mut self) -> String {
+            let mut received = Vec::new();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while Instant::now() < deadline {
+                self.stub.poll(&mut self.chip8, &mut self.debugger);
+                let mut buffer = [0; 4096];
+                match self.client.read(&mut buffer) {
+                    Ok(len) => received.extend_from_slice(&buffer[..len]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => panic!("{}", e),
+                }
+                let text = String::from_utf8_lossy(&received).into_owned();
+                let text = text.trim_start_matches('+');
+                if let Some(end) = text.find('#')
+                    && text.len() >= end + 3
+                {
+                    let body = &text[1..end];
+                    assert_eq!(
+                        &text[end + 1..end + 3],
+                        format!("{:02x}", checksum_of(body.as_bytes()))
+                    );
+                    return body.to_string();
+                }
+            }
+            panic!("No reply");
+        }
+
+        fn exchange(&mut self, command: &str) -> String {
+            let packet = format!("${}#{:02x}", command, checksum_of(command.as_bytes()));
+            self.send(packet.as_bytes());
+            self.reply()
+        }
+
+        // Waits for the stub to pick up a continue or step, then runs the
+        // program as the frontend does until the debugger pauses
+        fn run(&mut self) {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while self.debugger.is_paused() {
+                assert!(Instant::now() < deadline, "Not resumed");
+                self.stub.poll(&mut self.chip8, &mut self.debugger);
+            }
+            for _ in 0..100 {
+                if self.debugger.should_break(&self.chip8.cpu) {
+                    return;
+                }
+                self.chip8.step(0).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut gdb = Harness::new();
+        assert!(
+            gdb.exchange("qSupported:swbreak+")
+                .contains("qXfer:features:read+")
+        );
+        assert_eq!(gdb.exchange("QStartNoAckMode"), "OK");
+        assert_eq!(gdb.exchange("?"), "S05");
+        let registers = gdb.exchange("g");
+        assert_eq!(registers.len(), (16 + 2 + 2 + 3) * 2);
+        assert_eq!(&registers[32..40], "00000002");
+        assert_eq!(gdb.exchange("p11"), "0002");
+        assert_eq!(gdb.exchange("P3=7f"), "OK");
+        assert_eq!(gdb.chip8.cpu.get_v()[3], 0x7F);
+        assert_eq!(gdb.exchange("P10=0003"), "OK");
+        assert_eq!(gdb.chip8.cpu.get_i(), 0x300);
+        assert_eq!(gdb.exchange("P12=11"), "E01");
+        assert_eq!(gdb.exchange("m200,4"), "60057001");
+        assert_eq!(gdb.exchange("M300,2:abcd"), "OK");
+        assert_eq!(gdb.chip8.get_memory()[0x300..0x302], [0xAB, 0xCD]);
+        assert_eq!(gdb.exchange("mfff,2"), "E01");
+        assert_eq!(gdb.exchange("vMustReplyEmpty"), "");
+    }
+
+    #[test]
+    fn test_target_xml() {
+        let mut gdb = Harness::new();
+        let first = gdb.exchange("qXfer:features:read:target.xml:0,14");
+        assert_eq!(first, "m<?xml version=\"1.0\"?");
+        let rest = gdb.exchange("qXfer:features:read:target.xml:14,1000");
+        assert!(rest.starts_with('l'));
+        assert!(rest.ends_with("</target>\n"));
+        assert!(rest.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>"));
+    }
+
+    #[test]
+    fn test_step_continue_and_breakpoints() {
+        let mut gdb = Harness::new();
+        gdb.send(b"$s#73");
+        gdb.run();
+        assert_eq!(gdb.reply(), "S05");
+        assert_eq!(gdb.chip8.cpu.get_pc(), 0x202);
+
+        assert_eq!(gdb.exchange("Z0,204,2"), "OK");
+        assert!(gdb.debugger.has_breakpoint(0x204));
+        gdb.send(b"$c#63");
+        gdb.run();
+        assert_eq!(gdb.reply(), "S05");
+        assert_eq!(gdb.chip8.cpu.get_pc(), 0x204);
+        assert_eq!(gdb.chip8.cpu.get_v()[0], 6);
+
+        assert_eq!(gdb.exchange("z0,204,2"), "OK");
+        assert_eq!(gdb.exchange("Z1,204,2"), "");
+        gdb.send(b"$c#63");
+        gdb.run();
+        assert!(!gdb.debugger.is_paused());
+        gdb.send(&[INTERRUPT]);
+        assert_eq!(gdb.reply(), "S02");
+        assert!(gdb.debugger.is_paused());
+    }
+
+    #[test]
+    fn test_bad_checksum_is_rejected() {
+        let mut gdb = Harness::new();
+        gdb.send(b"$g#00");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut buffer = [0; 16];
+        loop {
+            gdb.stub.poll(&mut gdb.chip8, &mut gdb.debugger);
+            match gdb.client.read(&mut buffer) {
+                Ok(len) => {
+                    assert_eq!(&buffer[..len], b"-");
+                    break;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    assert!(Instant::now() < deadline, "No reply");
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+}
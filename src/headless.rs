@@ -0,0 +1,260 @@
+use crate::freq_timer::FrequencyTimer;
+use anyhow::{Result, anyhow};
+use core::{Chip8, CpuFault, Registers, StepOutcome, StopReason};
+use serde_json::{Value, json};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+const FRAME_MS: f32 = 1000.0 / 60.0;
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+const PNG_PALETTE: [[u8; 3]; 4] = [
+    [0x10, 0x10, 0x10],
+    [0x00, 0xFF, 0x00],
+    [0x00, 0xA0, 0xFF],
+    [0xFF, 0xFF, 0xFF],
+];
+
+// Each line is a frame number followed by the hex keys held from that frame on,
+// e.g. `60 5 6` holds 5 and 6 and a bare `90` releases everything.
+pub struct Script {
+    changes: Vec<(u32, u16)>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut changes: Vec<(u32, u16)> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(frame) = fields.next() else {
+                continue;
+            };
+            let error = |message: String| anyhow!("Input script line {}: {}", number + 1, message);
+            let frame: u32 = frame
+                .parse()
+                .map_err(|_| error(format!("invalid frame number `{}`", frame)))?;
+            if changes.last().is_some_and(|&(last, _)| frame < last) {
+                return Err(error("frames must be in ascending order".to_string()));
+            }
+            let mut keys = 0;
+            for key in fields {
+                let index = u8::from_str_radix(key, 16)
+                    .ok()
+                    .filter(|&index| index < 16)
+                    .ok_or_else(|| error(format!("invalid key `{}`", key)))?;
+                keys |= 1 << index;
+            }
+            changes.push((frame, keys));
+        }
+        Ok(Self { changes })
+    }
+
+    // Holds `frames[n]` during frame n, and nothing after the last one
+    pub fn from_frames(frames: &[u16]) -> Self {
+        let mut changes: Vec<(u32, u16)> = Vec::new();
+        for (frame, &keys) in frames.iter().chain(&[0]).enumerate() {
+            if changes.last().map_or(keys != 0, |&(_, last)| keys != last) {
+                changes.push((frame as u32, keys));
+            }
+        }
+        Self { changes }
+    }
+
+    pub fn keys_at(&self, frame: u32) -> u16 {
+        let index = self.changes.partition_point(|&(start, _)| start <= frame);
+        index
+            .checked_sub(1)
+            .map_or(0, |index| self.changes[index].1)
+    }
+}
+
+pub struct Summary {
+    // Frames completed, fewer than requested when the program exits or stops
+    pub frames: u32,
+    pub fault: Option<CpuFault>,
+    pub stop: Option<StopReason>,
+}
+
+// Instructions to run in the next frame of the virtual clock
+pub fn frame_steps(cpu_freq: &mut FrequencyTimer) -> usize {
+    cpu_freq.update(FRAME_MS)
+}
+
+// Ticks the machine over to the next frame once the frame's instructions have run
+pub fn end_frame(chip8: &mut Chip8) {
+    chip8.vblank();
+    chip8.dec_delay_timer();
+    chip8.dec_sound_timer();
+}
+
+// Runs up to `frames` frames on a virtual 60 Hz clock
+pub fn run(chip8: &mut Chip8, ips: u32, frames: u32, script: &Script) -> Summary {
+    let mut cpu_freq = FrequencyTimer::new(ips);
+    let summary = |frames, fault, stop| Summary {
+        frames,
+        fault,
+        stop,
+    };
+    for frame in 0..frames {
+        let key = script.keys_at(frame);
+        for _ in 0..frame_steps(&mut cpu_freq) {
+            match chip8.step(key) {
+                Ok(StepOutcome::Exited) => return summary(frame, None, None),
+                Ok(StepOutcome::Stopped(reason)) => return summary(frame, None, Some(reason)),
+                Ok(_) => {}
+                Err(fault) => return summary(frame, Some(fault), None),
+            }
+        }
+        end_frame(chip8);
+    }
+    summary(frames, None, None)
+}
+
+pub fn ascii(display: &[Vec<u8>]) -> String {
+    display
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|&pixel| ASCII_PIXELS[pixel as usize & 0b11])
+                .chain(Some('\n'))
+                .collect::<String>()
+        })
+        .collect()
+}
+
+pub fn write_png(display: &[Vec<u8>], path: &Path) -> Result<()> {
+    let height = display.len();
+    let width = display.first().map_or(0, Vec::len);
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        width as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels: Vec<u8> = display
+        .iter()
+        .flatten()
+        .flat_map(|&pixel| PNG_PALETTE[pixel as usize & 0b11])
+        .collect();
+    encoder.write_header()?.write_image_data(&pixels)?;
+    Ok(())
+}
+
+pub fn registers_json(registers: &Registers, summary: &Summary) -> Value {
+    json!({
+        "frames": summary.frames,
+        "fault": summary.fault.as_ref().map(CpuFault::to_string),
+        "stop": summary.stop.as_ref().map(StopReason::to_string),
+        "pc": registers.pc,
+        "i": registers.i,
+        "sp": registers.sp,
+        "v": registers.v,
+        "stack": registers.stack,
+        "dt": registers.dt,
+        "st": registers.st,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{Platform, Quirks};
+
+    #[test]
+    fn test_parse_script() {
+        let script = Script::parse("# frame keys\n10 5 a\n\n20 F # fire\n30\n").unwrap();
+        assert_eq!(script.keys_at(0), 0);
+        assert_eq!(script.keys_at(10), 0b0000_0100_0010_0000);
+        assert_eq!(script.keys_at(19), 0b0000_0100_0010_0000);
+        assert_eq!(script.keys_at(25), 0x8000);
+        assert_eq!(script.keys_at(1000), 0);
+    }
+
+    #[test]
+    fn test_parse_script_errors() {
+        let error = Script::parse("10 5\n5 6\n").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Input script line 2: frames must be in ascending order"
+        );
+        let error = Script::parse("x 5\n").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Input script line 1: invalid frame number `x`"
+        );
+        let error = Script::parse("1 10\n").err().unwrap();
+        assert_eq!(error.to_string(), "Input script line 1: invalid key `10`");
+    }
+
+    #[test]
+    fn test_script_from_frames() {
+        let script = Script::from_frames(&[0, 0, 0x10, 0x10, 0x30, 0]);
+        let keys: Vec<u16> = (0..8).map(|frame| script.keys_at(frame)).collect();
+        assert_eq!(keys, [0, 0, 0x10, 0x10, 0x30, 0, 0, 0]);
+        assert_eq!(script.changes, [(2, 0x10), (4, 0x30), (5, 0)]);
+        assert_eq!(Script::from_frames(&[1]).keys_at(1), 0);
+    }
+
+    #[test]
+    fn test_run_with_script() {
+        // Waits for a key, draws its font sprite, then exits
+        let program = [0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x00, 0xFD];
+        let mut chip8 = Chip8::new(&program, Platform::SuperChip, Quirks::default());
+        let script = Script::parse("3 1\n").unwrap();
+        let summary = run(&mut chip8, 600, 10, &script);
+        assert_eq!((summary.frames, summary.fault), (3, None));
+        let screen = ascii(chip8.get_display());
+        let rows: Vec<&str> = screen.lines().map(|row| &row[..4]).take(5).collect();
+        assert_eq!(rows, ["..#.", ".##.", "..#.", "..#.", ".###"]);
+        assert_eq!(chip8.registers().v[0], 1);
+    }
+
+    #[test]
+    fn test_registers_json() {
+        let mut chip8 = Chip8::new(
+            &[0x6A, 0x42, 0x12, 0x02],
+            Platform::Chip8,
+            Quirks::default(),
+        );
+        let summary = run(&mut chip8, 60, 2, &Script::parse("").unwrap());
+        let value = registers_json(&chip8.registers(), &summary);
+        assert_eq!(value["pc"], 0x202);
+        assert_eq!(value["v"][10], 0x42);
+        assert_eq!(value["frames"], 2);
+        assert!(value["fault"].is_null());
+        assert!(value["stop"].is_null());
+    }
+
+    #[test]
+    fn test_run_stops_on_fault() {
+        let mut chip8 = Chip8::new(&[0x00, 0xEE], Platform::Chip8, Quirks::default());
+        let summary = run(&mut chip8, 60, 5, &Script::parse("").unwrap());
+        assert_eq!(summary.frames, 0);
+        assert_eq!(summary.fault, Some(CpuFault::StackUnderflow { pc: 0x200 }));
+    }
+
+    #[test]
+    fn test_run_stops_on_watchpoint() {
+        // Counts V0 up forever
+        let mut chip8 = Chip8::new(
+            &[0x70, 0x01, 0x12, 0x00],
+            Platform::Chip8,
+            Quirks::default(),
+        );
+        chip8.add_watchpoint("v0=100".parse().unwrap());
+        let summary = run(&mut chip8, 600, 60, &Script::parse("").unwrap());
+        assert_eq!(summary.frames, 19);
+        assert_eq!(
+            summary.stop,
+            Some(StopReason::Register {
+                pc: 0x200,
+                x: 0,
+                value: 100
+            })
+        );
+        let value = registers_json(&chip8.registers(), &summary);
+        assert_eq!(value["stop"], "V0 reached 0x64 at PC 0x200");
+    }
+}
@@ -0,0 +1,397 @@
+use crate::gamepad::GamepadMap;
+use anyhow::{Result, anyhow};
+use ggez::input::keyboard::KeyCode;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+// The hex keypad as laid out on the COSMAC VIP, row by row
+pub const KEYPAD: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+// Host keys that can be bound, by the names used in key map files
+const KEY_NAMES: [(&str, KeyCode); 80] = [
+    ("Key0", KeyCode::Key0),
+    ("Key1", KeyCode::Key1),
+    ("Key2", KeyCode::Key2),
+    ("Key3", KeyCode::Key3),
+    ("Key4", KeyCode::Key4),
+    ("Key5", KeyCode::Key5),
+    ("Key6", KeyCode::Key6),
+    ("Key7", KeyCode::Key7),
+    ("Key8", KeyCode::Key8),
+    ("Key9", KeyCode::Key9),
+    ("A", KeyCode::A),
+    ("B", KeyCode::B),
+    ("C", KeyCode::C),
+    ("D", KeyCode::D),
+    ("E", KeyCode::E),
+    ("F", KeyCode::F),
+    ("G", KeyCode::G),
+    ("H", KeyCode::H),
+    ("I", KeyCode::I),
+    ("J", KeyCode::J),
+    ("K", KeyCode::K),
+    ("L", KeyCode::L),
+    ("M", KeyCode::M),
+    ("N", KeyCode::N),
+    ("O", KeyCode::O),
+    ("P", KeyCode::P),
+    ("Q", KeyCode::Q),
+    ("R", KeyCode::R),
+    ("S", KeyCode::S),
+    ("T", KeyCode::T),
+    ("U", KeyCode::U),
+    ("V", KeyCode::V),
+    ("W", KeyCode::W),
+    ("X", KeyCode::X),
+    ("Y", KeyCode::Y),
+    ("Z", KeyCode::Z),
+    ("Numpad0", KeyCode::Numpad0),
+    ("Numpad1", KeyCode::Numpad1),
+    ("Numpad2", KeyCode::Numpad2),
+    ("Numpad3", KeyCode::Numpad3),
+    ("Numpad4", KeyCode::Numpad4),
+    ("Numpad5", KeyCode::Numpad5),
+    ("Numpad6", KeyCode::Numpad6),
+    ("Numpad7", KeyCode::Numpad7),
+    ("Numpad8", KeyCode::Numpad8),
+    ("Numpad9", KeyCode::Numpad9),
+    ("NumpadAdd", KeyCode::NumpadAdd),
+    ("NumpadSubtract", KeyCode::NumpadSubtract),
+    ("NumpadMultiply", KeyCode::NumpadMultiply),
+    ("NumpadDivide", KeyCode::NumpadDivide),
+    ("NumpadDecimal", KeyCode::NumpadDecimal),
+    ("NumpadEnter", KeyCode::NumpadEnter),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Space", KeyCode::Space),
+    ("Return", KeyCode::Return),
+    ("Tab", KeyCode::Tab),
+    ("LShift", KeyCode::LShift),
+    ("RShift", KeyCode::RShift),
+    ("LControl", KeyCode::LControl),
+    ("RControl", KeyCode::RControl),
+    ("LAlt", KeyCode::LAlt),
+    ("RAlt", KeyCode::RAlt),
+    ("Comma", KeyCode::Comma),
+    ("Period", KeyCode::Period),
+    ("Slash", KeyCode::Slash),
+    ("Semicolon", KeyCode::Semicolon),
+    ("Apostrophe", KeyCode::Apostrophe),
+    ("Backslash", KeyCode::Backslash),
+    ("Grave", KeyCode::Grave),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("LBracket", KeyCode::LBracket),
+    ("RBracket", KeyCode::RBracket),
+];
+
+pub fn key_name(keycode: KeyCode) -> Option<&'static str> {
+    KEY_NAMES
+        .iter()
+        .find(|&&(_, k)| k == keycode)
+        .map(|&(name, _)| name)
+}
+
+fn parse_key(name: &str) -> Result<KeyCode> {
+    KEY_NAMES
+        .iter()
+        .find(|&&(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, k)| k)
+        .ok_or_else(|| anyhow!("unknown host key `{}`", name))
+}
+
+// The bindings of each CHIP-8 key in a `[keys]` style table, by the key's value
+pub fn parse_bindings<T>(
+    section: &str,
+    table: &Value,
+    parse: impl Fn(&str) -> Result<T>,
+) -> Result<Vec<(usize, Vec<T>)>> {
+    let table = table
+        .as_table()
+        .ok_or_else(|| anyhow!("`{}` must be a table", section))?;
+    let mut parsed = Vec::new();
+    for (key, bindings) in table {
+        let index = u8::from_str_radix(key, 16)
+            .ok()
+            .filter(|&index| key.len() == 1 && index < 16)
+            .ok_or_else(|| anyhow!("invalid CHIP-8 key `{}`", key))?;
+        let names = match bindings {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Option<_>>()
+                .ok_or_else(|| anyhow!("key {} must be bound to names", key))?,
+            _ => return Err(anyhow!("key {} must be bound to a name or a list", key)),
+        };
+        let bindings = names.into_iter().map(&parse).collect::<Result<_>>()?;
+        parsed.push((index as usize, bindings));
+    }
+    Ok(parsed)
+}
+
+pub fn format_bindings<T: Copy>(
+    section: &str,
+    keys: &[Vec<T>; 16],
+    name: impl Fn(T) -> Option<&'static str>,
+) -> String {
+    let mut text = format!("[{}]\n", section);
+    for (key, bindings) in keys.iter().enumerate() {
+        let names: Vec<String> = bindings
+            .iter()
+            .filter_map(|&binding| name(binding))
+            .map(|name| format!("\"{}\"", name))
+            .collect();
+        text += &format!("{:X} = [{}]\n", key, names.join(", "));
+    }
+    text
+}
+
+// The host keys bound to each CHIP-8 key, indexed by its value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Vec<KeyCode>; 16],
+    pub gamepad: GamepadMap,
+}
+
+// 1 2 3 C -> 1 2 3 4
+// 4 5 6 D -> Q W E R
+// 7 8 9 E -> A S D F
+// A 0 B F -> Z X C V
+impl Default for Keymap {
+    fn default() -> Self {
+        let keys = [
+            KeyCode::X,
+            KeyCode::Key1,
+            KeyCode::Key2,
+            KeyCode::Key3,
+            KeyCode::Q,
+            KeyCode::W,
+            KeyCode::E,
+            KeyCode::A,
+            KeyCode::S,
+            KeyCode::D,
+            KeyCode::Z,
+            KeyCode::C,
+            KeyCode::Key4,
+            KeyCode::R,
+            KeyCode::F,
+            KeyCode::V,
+        ];
+        Self {
+            keys: keys.map(|key| vec![key]),
+            gamepad: GamepadMap::default(),
+        }
+    }
+}
+
+impl Keymap {
+    // Replaces the bindings of the keys the file lists, e.g.
+    //
+    //     [keys]
+    //     5 = "W"
+    //     A = ["Z", "Space"]
+    //
+    //     [gamepad]
+    //     5 = "South"
+    //
+    // Host keys in `reserved` are hotkeys and can't be bound.
+    pub fn apply(&mut self, text: &str, reserved: &[KeyCode]) -> Result<()> {
+        let value: Value = text.parse()?;
+        if let Some(table) = value.get("keys") {
+            for (key, bindings) in parse_bindings("keys", table, parse_key)? {
+                if let Some(&host) = bindings.iter().find(|host| reserved.contains(host)) {
+                    return Err(anyhow!(
+                        "`{}` is a hotkey and cannot be bound to key {:X}",
+                        key_name(host).unwrap_or_default(),
+                        key
+                    ));
+                }
+                self.keys[key] = bindings;
+            }
+        }
+        if let Some(table) = value.get("gamepad") {
+            self.gamepad.apply(table)?;
+        }
+        Ok(())
+    }
+
+    pub fn apply_file(&mut self, path: &Path, reserved: &[KeyCode]) -> Result<()> {
+        let text = fs::read_to_string(path)?;
+        self.apply(&text, reserved)
+            .map_err(|e| anyhow!("Key map {}: {}", path.display(), e))
+    }
+
+    pub fn to_toml(&self) -> String {
+        let keys = format_bindings("keys", &self.keys, key_name);
+        format!("{}\n{}", keys, self.gamepad.to_toml())
+    }
+
+    pub fn bindings(&self, key: u8) -> &[KeyCode] {
+        &self.keys[key as usize]
+    }
+
+    // Makes `host` the only binding of `key`, taking it from any other key
+    pub fn bind(&mut self, key: u8, host: KeyCode) {
+        for bindings in &mut self.keys {
+            bindings.retain(|&k| k != host);
+        }
+        self.keys[key as usize] = vec![host];
+    }
+
+    pub fn mask(&self, is_pressed: impl Fn(KeyCode) -> bool) -> u16 {
+        self.keys.iter().enumerate().fold(0, |acc, (i, bindings)| {
+            let pressed = bindings.iter().any(|&k| is_pressed(k));
+            acc | if pressed { 1 << i } else { 0 }
+        })
+    }
+}
+
+// The global file, e.g. `~/.config/chip8/keymap.toml` on Linux
+pub fn global_path() -> Option<PathBuf> {
+    let dirs = directories::ProjectDirs::from("", "", "chip8")?;
+    Some(dirs.config_dir().join("keymap.toml"))
+}
+
+// Per-ROM overrides live next to the ROM, e.g. `game.ch8` -> `game.keymap.toml`
+pub fn rom_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("keymap.toml")
+}
+
+// Applies the global file, then the ROM's, then `extra`, skipping those that
+// don't exist. Returns the map with the most specific file it read, which is
+// where rebinding saves to.
+pub fn load(
+    rom: &Path,
+    extra: Option<&Path>,
+    reserved: &[KeyCode],
+) -> Result<(Keymap, Option<PathBuf>)> {
+    let mut keymap = Keymap::default();
+    let mut source = None;
+    let paths = [global_path(), Some(rom_path(rom))];
+    for path in paths.into_iter().flatten() {
+        if path.exists() {
+            keymap.apply_file(&path, reserved)?;
+            source = Some(path);
+        }
+    }
+    if let Some(path) = extra {
+        keymap.apply_file(path, reserved)?;
+        source = Some(path.to_path_buf());
+    }
+    Ok((keymap, source))
+}
+
+// Walks the keypad capturing one host key for each CHIP-8 key
+pub struct Rebinding {
+    position: usize,
+    original: Keymap,
+}
+
+impl Rebinding {
+    pub fn new(keymap: &Keymap) -> Self {
+        Self {
+            position: 0,
+            original: keymap.clone(),
+        }
+    }
+
+    // The CHIP-8 key waiting for a host key
+    pub fn key(&self) -> u8 {
+        KEYPAD[self.position]
+    }
+
+    // Binds the key and moves to the next; returns true after the last one
+    pub fn capture(&mut self, keymap: &mut Keymap, host: KeyCode) -> bool {
+        keymap.bind(self.key(), host);
+        self.position += 1;
+        self.position == KEYPAD.len()
+    }
+
+    // The map as it was before rebinding started
+    pub fn cancel(self) -> Keymap {
+        self.original
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let mut keymap = Keymap::default();
+        keymap
+            .apply("[keys]\n5 = \"Up\"\nA = [\"z\", \"Space\"]\n", &[])
+            .unwrap();
+        assert_eq!(keymap.bindings(5), [KeyCode::Up]);
+        assert_eq!(keymap.bindings(0xA), [KeyCode::Z, KeyCode::Space]);
+        assert_eq!(keymap.bindings(0), [KeyCode::X]);
+        let round_trip = {
+            let mut parsed = Keymap::default();
+            parsed.apply(&keymap.to_toml(), &[]).unwrap();
+            parsed
+        };
+        assert_eq!(round_trip, keymap);
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let error = |text: &str| {
+            Keymap::default()
+                .apply(text, &[KeyCode::Tab])
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error("[keys]\nG = \"A\"\n"), "invalid CHIP-8 key `G`");
+        assert_eq!(error("[keys]\n1 = \"Hyper\"\n"), "unknown host key `Hyper`");
+        assert_eq!(
+            error("[keys]\n1 = 5\n"),
+            "key 1 must be bound to a name or a list"
+        );
+        assert_eq!(
+            error("[keys]\n5 = [\"W\", \"Tab\"]\n"),
+            "`Tab` is a hotkey and cannot be bound to key 5"
+        );
+        assert!(Keymap::default().apply("", &[]).is_ok());
+    }
+
+    #[test]
+    fn test_mask_and_bind() {
+        let mut keymap = Keymap::default();
+        let mask = keymap.mask(|k| k == KeyCode::X || k == KeyCode::V);
+        assert_eq!(mask, 0x8001);
+        // Taking X for key 5 unbinds it from key 0
+        keymap.bind(5, KeyCode::X);
+        assert_eq!(keymap.mask(|k| k == KeyCode::X), 0x0020);
+        assert!(keymap.bindings(0).is_empty());
+    }
+
+    #[test]
+    fn test_rebinding() {
+        let mut keymap = Keymap::default();
+        let mut rebinding = Rebinding::new(&keymap);
+        assert_eq!(rebinding.key(), 1);
+        for &(_, host) in &KEY_NAMES[10..25] {
+            assert!(!rebinding.capture(&mut keymap, host));
+        }
+        assert_eq!(rebinding.key(), 0xF);
+        assert!(rebinding.capture(&mut keymap, KeyCode::Space));
+        assert_eq!(keymap.bindings(1), [KeyCode::A]);
+        assert_eq!(keymap.bindings(0xF), [KeyCode::Space]);
+
+        let mut rebinding = Rebinding::new(&keymap);
+        rebinding.capture(&mut keymap, KeyCode::Up);
+        assert_eq!(keymap.bindings(1), [KeyCode::Up]);
+        assert_eq!(rebinding.cancel().bindings(1), [KeyCode::A]);
+    }
+}
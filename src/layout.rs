@@ -0,0 +1,135 @@
+use clap::ValueEnum;
+
+// A pixel and the gap after it, in proportion
+const SIZE: f32 = 14.0;
+const SPACE: f32 = 2.0;
+// Window pixels per low-res CHIP-8 pixel unless `--scale` is given
+pub const DEFAULT_SCALE: u32 = 16;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scaling {
+    /// A whole number of window pixels per CHIP-8 pixel, so all are the same size
+    Integer,
+    /// As large as the window allows
+    Fit,
+}
+
+// Where the display's pixels go in the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub x: f32,
+    pub y: f32,
+    // Distance from one pixel to the next
+    pub cell: f32,
+    // Width and height of a pixel, less than `cell` when there is a gap
+    pub size: f32,
+}
+
+impl Layout {
+    // Top left corner of the pixel in column `x` and row `y`
+    pub fn pixel(&self, x: usize, y: usize) -> (f32, f32) {
+        (self.x + x as f32 * self.cell, self.y + y as f32 * self.cell)
+    }
+}
+
+// Scales a display of `columns` x `rows` pixels to `area` and centres it, leaving
+// black bars along the sides that don't fill. An empty area, as when the window
+// is minimized, gets pixels of size 0.
+pub fn layout(
+    area: (f32, f32),
+    columns: usize,
+    rows: usize,
+    scaling: Scaling,
+    gap: bool,
+) -> Layout {
+    let (width, height) = area;
+    if width <= 0.0 || height <= 0.0 {
+        return Layout {
+            x: 0.0,
+            y: 0.0,
+            cell: 0.0,
+            size: 0.0,
+        };
+    }
+    let (columns, rows) = (columns as f32, rows as f32);
+    let fit = (width / columns).min(height / rows).max(0.0);
+    let integer = scaling == Scaling::Integer;
+    let cell = if integer { fit.floor().max(1.0) } else { fit };
+    let mut size = if gap {
+        cell * SIZE / (SIZE + SPACE)
+    } else {
+        cell
+    };
+    if integer {
+        size = size.round().max(1.0);
+    }
+    // The gap after the last pixel on each line is left out when centring
+    let x = (width - (columns - 1.0) * cell - size) / 2.0;
+    let y = (height - (rows - 1.0) * cell - size) / 2.0;
+    if integer {
+        Layout {
+            x: x.floor(),
+            y: y.floor(),
+            cell,
+            size,
+        }
+    } else {
+        Layout { x, y, cell, size }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_window() {
+        let area = (64.0 * 16.0, 32.0 * 16.0);
+        let lores = layout(area, 64, 32, Scaling::Integer, true);
+        assert_eq!(
+            lores,
+            Layout {
+                x: 1.0,
+                y: 1.0,
+                cell: 16.0,
+                size: 14.0
+            }
+        );
+        // Hi-res pixels are half the size, so the display stays the same
+        let hires = layout(area, 128, 64, Scaling::Integer, true);
+        assert_eq!((hires.cell, hires.size), (8.0, 7.0));
+        assert_eq!(hires.pixel(127, 63), (1016.0, 504.0));
+    }
+
+    #[test]
+    fn test_letterbox() {
+        // A wide window puts bars at the sides
+        let wide = layout((1920.0, 1080.0), 64, 32, Scaling::Fit, false);
+        assert_eq!((wide.cell, wide.size), (30.0, 30.0));
+        assert_eq!((wide.x, wide.y), (0.0, 60.0));
+        let wide = layout((1920.0, 1080.0), 64, 32, Scaling::Integer, false);
+        assert_eq!((wide.x, wide.y), (0.0, 60.0));
+
+        // A tall one puts them above and below
+        let tall = layout((700.0, 1000.0), 64, 32, Scaling::Fit, false);
+        assert_eq!(tall.cell, 700.0 / 64.0);
+        assert_eq!(tall.x, 0.0);
+        assert_eq!(tall.y, 325.0);
+        let tall = layout((700.0, 1000.0), 64, 32, Scaling::Integer, false);
+        assert_eq!(tall.cell, 10.0);
+        assert_eq!((tall.x, tall.y), (30.0, 340.0));
+    }
+
+    #[test]
+    fn test_tiny_window() {
+        // Pixels never vanish while there is room, even too little for them
+        let tiny = layout((10.0, 10.0), 64, 32, Scaling::Integer, true);
+        assert_eq!((tiny.cell, tiny.size), (1.0, 1.0));
+        for scaling in [Scaling::Integer, Scaling::Fit] {
+            let minimized = layout((0.0, 0.0), 64, 32, scaling, true);
+            assert_eq!((minimized.cell, minimized.size), (0.0, 0.0));
+            let narrow = layout((0.0, 480.0), 64, 32, scaling, true);
+            assert_eq!(narrow.size, 0.0);
+        }
+    }
+}
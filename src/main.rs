@@ -1,38 +1,495 @@
-use crate::state::{SIZE, SPACE, State};
+use crate::beeper::{Beeper, Waveform};
+use crate::gdb::GdbStub;
+use crate::headless::Script;
+use crate::layout::{DEFAULT_SCALE, Scaling};
+use crate::movie::{Movie, MovieMode};
+use crate::rewind::Rewind;
+use crate::state::{Options, PANEL_WIDTH, State, title};
 use anyhow::Result;
-use clap::Parser;
-use ggez::conf::WindowMode;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use core::{Chip8, Platform, Quirks, SplitMix64, Tracer, Watchpoint, asm};
+use ggez::conf::{FullscreenType, WindowMode};
 use ggez::{conf::WindowSetup, *};
-use std::fs::File;
-use std::io::Read;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+mod beeper;
+mod debugger;
+mod disasm;
 mod freq_timer;
+mod gamepad;
+mod gdb;
+mod headless;
+mod keymap;
+mod layout;
+mod movie;
+mod palette;
+mod rewind;
+mod sound;
 mod state;
+mod trace_diff;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum QuirksProfile {
+    Vip,
+    Schip,
+    Xochip,
+}
+
+impl QuirksProfile {
+    fn quirks(self) -> Quirks {
+        match self {
+            QuirksProfile::Vip => Quirks::VIP,
+            QuirksProfile::Schip => Quirks::SUPER_CHIP,
+            QuirksProfile::Xochip => Quirks::XO_CHIP,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PlatformArg {
+    Chip8,
+    Schip,
+    Xochip,
+}
+
+impl PlatformArg {
+    fn platform(self) -> Platform {
+        match self {
+            PlatformArg::Chip8 => Platform::Chip8,
+            PlatformArg::Schip => Platform::SuperChip,
+            PlatformArg::Xochip => Platform::XoChip,
+        }
+    }
+}
 
 #[derive(Parser, Debug)]
-struct Args {
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    // `chip8 ROM` is shorthand for `chip8 run ROM`
+    #[command(flatten)]
+    run: Option<RunArgs>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Run(Box<RunArgs>),
+    Disasm(DisasmArgs),
+    Asm(AsmArgs),
+    TraceDiff(TraceDiffArgs),
+}
+
+#[derive(Args, Debug)]
+struct AsmArgs {
+    source_path: PathBuf,
+    /// Defaults to the source path with a `.ch8` extension
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct DisasmArgs {
+    rom_path: String,
+    #[arg(long, value_enum, default_value_t = disasm::Format::Text)]
+    format: disasm::Format,
+}
+
+#[derive(Args, Debug)]
+struct TraceDiffArgs {
+    /// Two logs written by `--trace`
+    #[arg(num_args = 2, required_unless_present = "rom")]
+    logs: Vec<PathBuf>,
+    /// Instead run this ROM under `--quirks-a` and `--quirks-b` in lockstep
+    #[arg(long, conflicts_with = "logs", requires_all = ["quirks_a", "quirks_b"])]
+    rom: Option<String>,
+    #[arg(long, value_enum)]
+    quirks_a: Option<QuirksProfile>,
+    #[arg(long, value_enum)]
+    quirks_b: Option<QuirksProfile>,
+    #[arg(long, value_enum, default_value_t = PlatformArg::Chip8)]
+    platform: PlatformArg,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    #[arg(long, default_value_t = 700, value_parser = clap::value_parser!(u32).range(1..))]
+    ips: u32,
+    #[arg(long, default_value_t = 600)]
+    frames: u32,
+    /// Key presses to apply, one `FRAME KEY...` line per change
+    #[arg(long, requires = "rom")]
+    input: Option<PathBuf>,
+    /// Instructions shown before and after the divergence
+    #[arg(long, default_value_t = 3)]
+    context: usize,
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
     rom_path: String,
+    #[arg(long, value_enum, default_value_t = PlatformArg::Chip8)]
+    platform: PlatformArg,
+    #[arg(long, value_enum)]
+    quirks: Option<QuirksProfile>,
+    /// Seed for CXNN so that runs are reproducible; random if omitted
+    #[arg(long)]
+    seed: Option<u64>,
+    #[arg(long, default_value_t = 700, value_parser = clap::value_parser!(u32).range(1..))]
+    ips: u32,
+    #[arg(long, conflicts_with = "ips", value_parser = clap::value_parser!(u32).range(1..=u32::MAX as i64 / 60))]
+    cycles_per_frame: Option<u32>,
+    #[arg(long)]
+    mute: bool,
+    #[arg(long, default_value_t = 440.0)]
+    tone: f32,
+    #[arg(long, default_value_t = 0.25)]
+    volume: f32,
+    #[arg(long, value_enum, default_value_t = Waveform::Square)]
+    waveform: Waveform,
+    /// Seconds of history kept for rewinding, 0 disables it
+    #[arg(long, default_value_t = 10)]
+    rewind_seconds: u32,
+    /// Memory budget for the rewind history in MiB
+    #[arg(long, default_value_t = 16)]
+    rewind_memory: usize,
+    /// Start paused with the debugger panel open
+    #[arg(long, conflicts_with = "headless")]
+    debug: bool,
+    /// Stop when `write:ADDR[-ADDR]`, `i:ADDR[-ADDR]`, `vX=VALUE` or `collision` becomes true;
+    /// opens the debugger unless headless
+    #[arg(long)]
+    watch: Vec<Watchpoint>,
+    /// Serve the GDB remote serial protocol on this local port; opens the debugger
+    #[arg(long, conflicts_with = "headless")]
+    gdb: Option<u16>,
+    /// Log every executed instruction to this file
+    #[arg(long)]
+    trace: Option<PathBuf>,
+    /// Only trace instructions at these addresses, given as `ADDR[-ADDR]`
+    #[arg(long, requires = "trace", value_parser = core::parse_range)]
+    trace_range: Vec<RangeInclusive<usize>>,
+    /// Key map file applied over the global and per-ROM ones; rebinding saves to it
+    #[arg(long)]
+    keymap: Option<PathBuf>,
+    /// Palette to start with, built in or from the theme file; Tab cycles through them
+    #[arg(long, conflicts_with = "headless")]
+    palette: Option<String>,
+    /// Custom palette as 2 or 4 `#RRGGBB` colors separated by commas, off color first
+    #[arg(long, conflicts_with_all = ["headless", "palette"], value_parser = palette::parse_colors)]
+    colors: Option<palette::Colors>,
+    /// Draw pixels without the gap between them
+    #[arg(long, conflicts_with = "headless")]
+    no_gap: bool,
+    /// Window pixels per low-res CHIP-8 pixel when the window opens
+    #[arg(long, default_value_t = DEFAULT_SCALE, conflicts_with = "headless", value_parser = clap::value_parser!(u32).range(1..))]
+    scale: u32,
+    /// How the display grows with the window
+    #[arg(long, value_enum, default_value_t = Scaling::Integer, conflicts_with = "headless")]
+    scaling: Scaling,
+    /// Start in fullscreen; F11 or Alt+Enter toggles it
+    #[arg(long, conflicts_with = "headless")]
+    fullscreen: bool,
+    /// Save the keys held each frame, with the seed and settings, to this movie file
+    #[arg(long, conflicts_with = "play")]
+    record: Option<PathBuf>,
+    /// Replay a movie from `--record`, with its settings rather than the command line's
+    #[arg(long, conflicts_with_all = ["platform", "quirks", "seed", "ips", "cycles_per_frame", "frames", "input"])]
+    play: Option<PathBuf>,
+    /// Run without a window on a virtual clock, then dump the screen and registers
+    #[arg(long)]
+    headless: bool,
+    #[arg(long, default_value_t = 600, requires = "headless")]
+    frames: u32,
+    /// Key presses to apply, one `FRAME KEY...` line per change
+    #[arg(long, requires = "headless")]
+    input: Option<PathBuf>,
+    /// Screen dump path, PNG if it ends in `.png` and ASCII otherwise; stdout if omitted
+    #[arg(long, requires = "headless")]
+    screen: Option<PathBuf>,
+    /// Register dump path as JSON; stdout if omitted
+    #[arg(long, requires = "headless")]
+    registers: Option<PathBuf>,
 }
 
-fn read_rom(path: &str) -> Result<Vec<u8>> {
+fn read_rom(path: &str, platform: Platform) -> Result<Vec<u8>> {
     let mut file = File::open(path)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
-    if buffer.len() > 0xE00 {
+    if buffer.len() > platform.max_program_len() {
         return Err(anyhow::anyhow!("ROM size exceeds memory limit"));
     }
     Ok(buffer)
 }
 
+fn open_tracer(args: &RunArgs) -> Result<Option<Tracer>> {
+    let Some(path) = &args.trace else {
+        return Ok(None);
+    };
+    let file = BufWriter::new(File::create(path)?);
+    Ok(Some(Tracer::new(file, args.trace_range.clone())))
+}
+
 fn main() -> Result<()> {
-    let args = Args::parse();
-    let rom = read_rom(&args.rom_path)?;
-    let state = State::new(&rom);
-    let width = ((SIZE + SPACE) * core::DISPLAY_WIDTH - SPACE) as f32;
-    let height = ((SIZE + SPACE) * core::DISPLAY_HEIGHT - SPACE) as f32;
+    let cli = Cli::parse();
+    match (cli.command, cli.run) {
+        (Some(Command::Run(args)), _) => run(*args),
+        (None, Some(args)) => run(args),
+        (Some(Command::Disasm(args)), _) => {
+            let rom = read_rom(&args.rom_path, Platform::XoChip)?;
+            print!("{}", disasm::render(&core::disassemble(&rom), args.format));
+            Ok(())
+        }
+        (Some(Command::Asm(args)), _) => assemble(args),
+        (Some(Command::TraceDiff(args)), _) => trace_diff(args),
+        (None, None) => {
+            Cli::command().print_help()?;
+            Ok(())
+        }
+    }
+}
+
+fn run(args: RunArgs) -> Result<()> {
+    let played = args.play.as_deref().map(Movie::load).transpose()?;
+    let (platform, quirks, ips, seed) = match &played {
+        Some(movie) => (movie.platform, movie.quirks, movie.ips, Some(movie.seed)),
+        None => {
+            let platform = args.platform.platform();
+            let quirks = args
+                .quirks
+                .map_or_else(|| platform.default_quirks(), QuirksProfile::quirks);
+            let ips = args.cycles_per_frame.map_or(args.ips, |cycles| cycles * 60);
+            // A recording has to know its seed to replay
+            let seed = args
+                .seed
+                .or_else(|| args.record.is_some().then(movie::random_seed));
+            (platform, quirks, ips, seed)
+        }
+    };
+    let rom = read_rom(&args.rom_path, platform)?;
+    let movie = match (played, &args.record, seed) {
+        (Some(movie), _, _) => {
+            movie.check_rom(&rom)?;
+            Some(MovieMode::Playing(movie))
+        }
+        (None, Some(path), Some(seed)) => Some(MovieMode::Recording {
+            movie: Movie::new(&rom, platform, quirks, seed, ips),
+            path: path.clone(),
+        }),
+        _ => None,
+    };
+    if args.headless {
+        return run_headless(&args, &rom, platform, quirks, ips, seed, movie);
+    }
+    if args.tone <= 0.0 {
+        return Err(anyhow::anyhow!("Tone frequency must be greater than 0"));
+    }
+    let mut width = (args.scale as usize * core::DISPLAY_WIDTH) as f32;
+    let gdb = match args.gdb {
+        Some(port) => {
+            let stub = GdbStub::bind(port)?;
+            println!("Waiting for GDB on {}", stub.local_addr()?);
+            Some(stub)
+        }
+        None => None,
+    };
+    let mut theme = palette::load()?;
+    if let Some(colors) = args.colors {
+        theme.add("custom", colors);
+        theme.select("custom")?;
+    }
+    if let Some(name) = &args.palette {
+        theme.select(name)?;
+    }
+    if args.no_gap {
+        theme.gap = false;
+    }
+    let debug = args.debug || !args.watch.is_empty() || gdb.is_some();
+    let (keymap, keymap_path) = keymap::load(
+        Path::new(&args.rom_path),
+        args.keymap.as_deref(),
+        &state::reserved_keys(debug),
+    )?;
+    if debug {
+        width += PANEL_WIDTH;
+    }
+    let height = (args.scale as usize * core::DISPLAY_HEIGHT) as f32;
+    let fullscreen = if args.fullscreen {
+        FullscreenType::Desktop
+    } else {
+        FullscreenType::Windowed
+    };
+    let window_mode = WindowMode::default()
+        .dimensions(width, height)
+        .resizable(true)
+        .fullscreen_type(fullscreen);
     let (ctx, event_loop) = ggez::ContextBuilder::new("chip8", "")
         .default_conf(ggez::conf::Conf::new())
-        .window_mode(WindowMode::default().dimensions(width, height))
-        .window_setup(WindowSetup::default().title("CHIP-8 Emulator"))
+        .window_mode(window_mode)
+        .window_setup(WindowSetup::default().title(&title(ips)))
         .build()?;
+    let beeper = Beeper::new(args.tone, args.volume, args.waveform);
+    let tracer = open_tracer(&args)?;
+    let options = Options {
+        rom_path: PathBuf::from(&args.rom_path),
+        platform,
+        quirks,
+        seed,
+        ips,
+        beeper,
+        muted: args.mute,
+        rewind: Rewind::new(args.rewind_seconds as usize * 60, args.rewind_memory << 20),
+        debug,
+        watchpoints: args.watch,
+        gdb,
+        tracer,
+        movie,
+        keymap,
+        keymap_path,
+        theme,
+        scaling: args.scaling,
+        fullscreen: args.fullscreen,
+    };
+    let state = State::new(&ctx, &rom, options);
     event::run(ctx, event_loop, state);
 }
+
+fn run_headless(
+    args: &RunArgs,
+    rom: &[u8],
+    platform: Platform,
+    quirks: Quirks,
+    ips: u32,
+    seed: Option<u64>,
+    movie: Option<MovieMode>,
+) -> Result<()> {
+    let mut chip8 = Chip8::new(rom, platform, quirks);
+    if let Some(seed) = seed {
+        chip8.set_random_source(SplitMix64::new(seed));
+    }
+    let (script, frames) = match (&movie, &args.input) {
+        (Some(MovieMode::Playing(movie)), _) => (
+            Script::from_frames(&movie.frames),
+            movie.frames.len() as u32,
+        ),
+        (_, Some(path)) => (Script::parse(&fs::read_to_string(path)?)?, args.frames),
+        (_, None) => (Script::parse("")?, args.frames),
+    };
+    for watchpoint in &args.watch {
+        chip8.add_watchpoint(watchpoint.clone());
+    }
+    chip8.set_tracer(open_tracer(args)?);
+    let summary = headless::run(&mut chip8, ips, frames, &script);
+    if let Some(tracer) = chip8.set_tracer(None) {
+        tracer.finish()?;
+    }
+    if let Some(MovieMode::Recording { mut movie, path }) = movie {
+        // Includes the frame the run stopped in, so the replay stops there too
+        let recorded = (summary.frames + 1).min(frames);
+        movie.frames = (0..recorded).map(|frame| script.keys_at(frame)).collect();
+        movie.save(&path)?;
+    }
+    match &args.screen {
+        Some(path) if path.extension().is_some_and(|ext| ext == "png") => {
+            headless::write_png(chip8.get_display(), path)?
+        }
+        Some(path) => fs::write(path, headless::ascii(chip8.get_display()))?,
+        None => print!("{}", headless::ascii(chip8.get_display())),
+    }
+    let registers = headless::registers_json(&chip8.registers(), &summary);
+    let registers = serde_json::to_string_pretty(&registers)?;
+    match &args.registers {
+        Some(path) => fs::write(path, registers + "\n")?,
+        None => println!("{}", registers),
+    }
+    match summary.fault {
+        Some(fault) => Err(fault.into()),
+        None => Ok(()),
+    }
+}
+
+// Includes are resolved relative to the directory of the main source file
+fn assemble(args: AsmArgs) -> Result<()> {
+    let source = fs::read_to_string(&args.source_path)?;
+    let name = args.source_path.display().to_string();
+    let dir = args.source_path.parent().unwrap_or(Path::new(""));
+    // Octo programs are recognised by their `.8o` extension
+    let rom = if args.source_path.extension().is_some_and(|ext| ext == "8o") {
+        asm::assemble_octo(&source, &name)?
+    } else {
+        asm::assemble_with(&source, &name, &mut |path| {
+            fs::read_to_string(dir.join(path)).map_err(|e| format!("{}: {}", path, e))
+        })?
+    };
+    if rom.len() > Platform::XoChip.max_program_len() {
+        return Err(anyhow::anyhow!("ROM size exceeds memory limit"));
+    }
+    let output = args
+        .output
+        .unwrap_or_else(|| args.source_path.with_extension("ch8"));
+    fs::write(&output, &rom)?;
+    println!("Wrote {} bytes to {}", rom.len(), output.display());
+    Ok(())
+}
+
+// Prints the first divergence and exits with status 1, so scripts can bisect on it
+fn trace_diff(args: TraceDiffArgs) -> Result<()> {
+    let Some(rom_path) = &args.rom else {
+        let (a, b) = (
+            fs::read_to_string(&args.logs[0])?,
+            fs::read_to_string(&args.logs[1])?,
+        );
+        let a: Vec<&str> = a.lines().filter(|line| !line.is_empty()).collect();
+        let b: Vec<&str> = b.lines().filter(|line| !line.is_empty()).collect();
+        match trace_diff::compare(&a, &b) {
+            Some(divergence) => {
+                print!("{}", trace_diff::report(&a, &b, &divergence, args.context));
+                std::process::exit(1);
+            }
+            None => println!("No divergence in {} instructions", a.len()),
+        }
+        return Ok(());
+    };
+    let platform = args.platform.platform();
+    let rom = read_rom(rom_path, platform)?;
+    let quirks = [args.quirks_a, args.quirks_b].map(|q| q.unwrap_or(QuirksProfile::Vip).quirks());
+    let script = match &args.input {
+        Some(path) => Script::parse(&fs::read_to_string(path)?)?,
+        None => Script::parse("")?,
+    };
+    let differences = quirks[0].differences(quirks[1]);
+    if differences.is_empty() {
+        println!("The profiles have the same quirks");
+    } else {
+        println!("Quirks that differ: {}", differences.join(", "));
+    }
+    let config = trace_diff::LockstepConfig {
+        rom: &rom,
+        platform,
+        quirks,
+        seed: args.seed,
+        ips: args.ips,
+        frames: args.frames,
+        script: &script,
+        context: args.context,
+    };
+    match trace_diff::lockstep(&config) {
+        trace_diff::Outcome::Matched(steps) => println!("No divergence in {} steps", steps),
+        trace_diff::Outcome::Diverged(divergence) => {
+            println!(
+                "First divergence at step {}, PC 0x{:03X} {}: {}",
+                divergence.steps,
+                divergence.pc,
+                divergence.instruction,
+                divergence.fields.join(", ")
+            );
+            for (side, lines) in ["a", "b"].iter().zip(&divergence.context) {
+                for line in lines {
+                    println!("{}  {}", side, line);
+                }
+            }
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,208 @@
+//! Movie files, which replay a run exactly.
+//!
+//! All integers are big-endian. Version 1 is laid out as:
+//!
+//! | Field        | Size       | Notes                                  |
+//! |--------------|------------|----------------------------------------|
+//! | magic        | 4          | `C8MV`                                 |
+//! | version      | 2          | currently 1                            |
+//! | ROM hash     | 8          | see `core::rom_hash`                   |
+//! | platform     | 1          | 0 CHIP-8, 1 SUPER-CHIP, 2 XO-CHIP      |
+//! | quirks       | 1          | bit flags, see `Quirks::to_bits`       |
+//! | seed         | 8          | for `SplitMix64`                       |
+//! | IPS          | 4          |                                        |
+//! | frame count  | 4          |                                        |
+//! | keys         | 2 * count  | the key mask held during each frame    |
+
+use anyhow::{Result, anyhow};
+use core::{Platform, Quirks};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 4] = b"C8MV";
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub ips: u32,
+    pub frames: Vec<u16>,
+}
+
+// A movie being recorded to `path` on exit, or one being played back
+pub enum MovieMode {
+    Recording { movie: Movie, path: PathBuf },
+    Playing(Movie),
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], platform: Platform, quirks: Quirks, seed: u64, ips: u32) -> Self {
+        Self {
+            rom_hash: core::rom_hash(rom),
+            platform,
+            quirks,
+            seed,
+            ips,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+            .map_err(|e| anyhow!("Failed to read movie {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        Ok(fs::write(path, self.to_bytes())?)
+    }
+
+    pub fn check_rom(&self, rom: &[u8]) -> Result<()> {
+        if core::rom_hash(rom) != self.rom_hash {
+            return Err(anyhow!("Movie was recorded with a different ROM"));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_LEN + self.frames.len() * 2);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_be_bytes());
+        data.extend_from_slice(&self.rom_hash.to_be_bytes());
+        data.push(match self.platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        });
+        data.push(self.quirks.to_bits());
+        data.extend_from_slice(&self.seed.to_be_bytes());
+        data.extend_from_slice(&self.ips.to_be_bytes());
+        data.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+        for keys in &self.frames {
+            data.extend_from_slice(&keys.to_be_bytes());
+        }
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let field = |start: usize, len: usize| {
+            data.get(start..start + len)
+                .ok_or_else(|| anyhow!("Movie is truncated"))
+        };
+        if field(0, 4)? != MAGIC {
+            return Err(anyhow!("Not a movie file"));
+        }
+        let version = u16::from_be_bytes(field(4, 2)?.try_into()?);
+        if version != VERSION {
+            return Err(anyhow!("Unsupported movie version: {}", version));
+        }
+        let platform = match field(14, 1)?[0] {
+            0 => Platform::Chip8,
+            1 => Platform::SuperChip,
+            2 => Platform::XoChip,
+            _ => return Err(anyhow!("Movie has an invalid platform")),
+        };
+        let ips = u32::from_be_bytes(field(24, 4)?.try_into()?);
+        if ips == 0 {
+            return Err(anyhow!("Movie has an invalid IPS"));
+        }
+        let count = u32::from_be_bytes(field(28, 4)?.try_into()?) as usize;
+        let frames = field(HEADER_LEN, count * 2)?
+            .chunks(2)
+            .map(|keys| u16::from_be_bytes([keys[0], keys[1]]))
+            .collect();
+        Ok(Self {
+            rom_hash: u64::from_be_bytes(field(6, 8)?.try_into()?),
+            platform,
+            quirks: Quirks::from_bits(field(15, 1)?[0]),
+            seed: u64::from_be_bytes(field(16, 8)?.try_into()?),
+            ips,
+            frames,
+        })
+    }
+}
+
+impl MovieMode {
+    // The keys to hold during `frame`. Recording keeps the live keys, dropping
+    // any frames after it that were undone by rewinding. Returns None once
+    // playback runs out.
+    pub fn keys(&mut self, frame: usize, live: u16) -> Option<u16> {
+        match self {
+            MovieMode::Recording { movie, .. } => {
+                movie.frames.truncate(frame);
+                movie.frames.push(live);
+                Some(live)
+            }
+            MovieMode::Playing(movie) => movie.frames.get(frame).copied(),
+        }
+    }
+}
+
+// Seeds a recording made without `--seed`, which must still replay the same
+pub fn random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie() -> Movie {
+        let mut movie = Movie::new(&[0x12, 0x00], Platform::XoChip, Quirks::XO_CHIP, 42, 1000);
+        movie.frames = vec![0, 0x0020, 0x8001];
+        movie
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let movie = movie();
+        let data = movie.to_bytes();
+        assert_eq!(data.len(), HEADER_LEN + 6);
+        assert_eq!(&data[..6], b"C8MV\x00\x01");
+        assert_eq!(Movie::from_bytes(&data).unwrap(), movie);
+        assert!(movie.check_rom(&[0x12, 0x00]).is_ok());
+        assert_eq!(
+            movie.check_rom(&[0x12, 0x02]).unwrap_err().to_string(),
+            "Movie was recorded with a different ROM"
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        let data = movie().to_bytes();
+        let error = |data: &[u8]| Movie::from_bytes(data).unwrap_err().to_string();
+        assert_eq!(error(&data[..data.len() - 1]), "Movie is truncated");
+        assert_eq!(error(b"C8SS"), "Not a movie file");
+        let mut data = data;
+        data[5] = 2;
+        assert_eq!(error(&data), "Unsupported movie version: 2");
+        data[5] = 1;
+        data[14] = 3;
+        assert_eq!(error(&data), "Movie has an invalid platform");
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut mode = MovieMode::Recording {
+            movie: movie(),
+            path: PathBuf::new(),
+        };
+        assert_eq!(mode.keys(3, 0x0004), Some(0x0004));
+        // Rewound to frame 1
+        assert_eq!(mode.keys(1, 0x0002), Some(0x0002));
+        let MovieMode::Recording { movie, .. } = mode else {
+            unreachable!()
+        };
+        assert_eq!(movie.frames, [0, 0x0002]);
+
+        let mut mode = MovieMode::Playing(movie);
+        assert_eq!(mode.keys(1, 0xFFFF), Some(0x0002));
+        assert_eq!(mode.keys(2, 0xFFFF), None);
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+// Colors for pixels lit on neither plane, the first, the second and both, as
+// 0xRRGGBB. Two-color displays only use the first two.
+pub type Colors = [u32; 4];
+
+const BUILTIN: [(&str, Colors); 5] = [
+    ("classic", [0x101010, 0x00FF00, 0x00A0FF, 0xFFFFFF]),
+    ("amber", [0x1A1000, 0xFFB000, 0x8A5A00, 0xFFE4A8]),
+    ("white", [0x000000, 0xFFFFFF, 0x808080, 0xC8C8C8]),
+    ("lcd", [0x9BBC0F, 0x0F380F, 0x6B8C0F, 0x306230]),
+    ("high-contrast", [0x000000, 0xFFFF00, 0x00FFFF, 0xFFFFFF]),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Colors,
+}
+
+// `#RRGGBB`, with the `#` optional
+pub fn parse_color(text: &str) -> Result<u32> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 {
+        return Err(anyhow!("invalid color `{}`", text));
+    }
+    u32::from_str_radix(hex, 16).map_err(|_| anyhow!("invalid color `{}`", text))
+}
+
+// Two or four colors separated by commas. With two, pixels on the second
+// plane use the first plane's color.
+pub fn parse_colors(text: &str) -> Result<Colors> {
+    let colors = text
+        .split(',')
+        .map(|color| parse_color(color.trim()))
+        .collect::<Result<Vec<u32>>>()?;
+    match colors[..] {
+        [off, on] => Ok([off, on, on, on]),
+        [off, first, second, both] => Ok([off, first, second, both]),
+        _ => Err(anyhow!(
+            "a palette needs 2 or 4 colors, not {}",
+            colors.len()
+        )),
+    }
+}
+
+// The palettes to cycle through, the one shown and whether pixels are spaced
+// apart
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Theme {
+    palettes: Vec<Palette>,
+    current: usize,
+    pub gap: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let palettes = BUILTIN
+            .iter()
+            .map(|&(name, colors)| Palette {
+                name: name.to_string(),
+                colors,
+            })
+            .collect();
+        Self {
+            palettes,
+            current: 0,
+            gap: true,
+        }
+    }
+}
+
+impl Theme {
+    // Applies the settings a theme file lists, e.g.
+    //
+    //     palette = "mine"
+    //     gap = false
+    //
+    //     [palettes]
+    //     mine = ["#000000", "#FFFFFF"]
+    pub fn apply(&mut self, text: &str) -> Result<()> {
+        let value: Value = text.parse()?;
+        if let Some(table) = value.get("palettes") {
+            let table = table
+                .as_table()
+                .ok_or_else(|| anyhow!("`palettes` must be a table"))?;
+            for (name, colors) in table {
+                let colors = colors
+                    .as_array()
+                    .and_then(|colors| {
+                        colors
+                            .iter()
+                            .map(Value::as_str)
+                            .collect::<Option<Vec<&str>>>()
+                    })
+                    .ok_or_else(|| anyhow!("palette `{}` must be a list of colors", name))?;
+                self.add(name, parse_colors(&colors.join(","))?);
+            }
+        }
+        if let Some(name) = value.get("palette") {
+            let name = name
+                .as_str()
+                .ok_or_else(|| anyhow!("`palette` must be a name"))?;
+            self.select(name)?;
+        }
+        if let Some(gap) = value.get("gap") {
+            self.gap = gap
+                .as_bool()
+                .ok_or_else(|| anyhow!("`gap` must be true or false"))?;
+        }
+        Ok(())
+    }
+
+    pub fn apply_file(&mut self, path: &Path) -> Result<()> {
+        let text = fs::read_to_string(path)?;
+        self.apply(&text)
+            .map_err(|e| anyhow!("Theme {}: {}", path.display(), e))
+    }
+
+    // Replaces the palette with this name, or adds it to the end
+    pub fn add(&mut self, name: &str, colors: Colors) {
+        match self.palettes.iter_mut().find(|p| p.name == name) {
+            Some(palette) => palette.colors = colors,
+            None => self.palettes.push(Palette {
+                name: name.to_string(),
+                colors,
+            }),
+        }
+    }
+
+    pub fn select(&mut self, name: &str) -> Result<()> {
+        self.current = self
+            .palettes
+            .iter()
+            .position(|p| p.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                let names: Vec<&str> = self.palettes.iter().map(|p| p.name.as_str()).collect();
+                anyhow!(
+                    "unknown palette `{}`, expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })?;
+        Ok(())
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palettes[self.current]
+    }
+
+    // Moves to the next palette, or the previous one, wrapping around
+    pub fn cycle(&mut self, forward: bool) -> &Palette {
+        let len = self.palettes.len();
+        self.current = if forward {
+            (self.current + 1) % len
+        } else {
+            (self.current + len - 1) % len
+        };
+        self.palette()
+    }
+}
+
+pub fn global_path() -> Option<PathBuf> {
+    let dirs = directories::ProjectDirs::from("", "", "chip8")?;
+    Some(dirs.config_dir().join("theme.toml"))
+}
+
+// Applies the global theme file if there is one
+pub fn load() -> Result<Theme> {
+    let mut theme = Theme::default();
+    if let Some(path) = global_path().filter(|path| path.exists()) {
+        theme.apply_file(&path)?;
+    }
+    Ok(theme)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_colors() {
+        assert_eq!(parse_color("#FFB000").unwrap(), 0xFFB000);
+        assert_eq!(parse_color("00a0ff").unwrap(), 0x00A0FF);
+        assert!(parse_color("#FFF").is_err());
+        assert!(parse_color("#GGGGGG").is_err());
+        assert_eq!(
+            parse_colors("#000000, #FFFFFF").unwrap(),
+            [0x000000, 0xFFFFFF, 0xFFFFFF, 0xFFFFFF]
+        );
+        assert_eq!(
+            parse_colors("000000,FF0000,0000FF,FFFFFF").unwrap(),
+            [0x000000, 0xFF0000, 0x0000FF, 0xFFFFFF]
+        );
+        assert_eq!(
+            parse_colors("#000000").unwrap_err().to_string(),
+            "a palette needs 2 or 4 colors, not 1"
+        );
+    }
+
+    #[test]
+    fn test_cycle() {
+        let mut theme = Theme::default();
+        assert_eq!(theme.palette().name, "classic");
+        assert_eq!(theme.cycle(true).name, "amber");
+        assert_eq!(theme.cycle(false).name, "classic");
+        assert_eq!(theme.cycle(false).name, "high-contrast");
+        assert_eq!(theme.cycle(true).name, "classic");
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut theme = Theme::default();
+        theme
+            .apply(
+                "palette = \"mine\"\ngap = false\n\n[palettes]\n\
+                 mine = [\"#000000\", \"#FFFFFF\"]\namber = [\"#000000\", \"#FFB000\"]\n",
+            )
+            .unwrap();
+        assert_eq!(
+            theme.palette().colors,
+            [0x000000, 0xFFFFFF, 0xFFFFFF, 0xFFFFFF]
+        );
+        assert!(!theme.gap);
+        // Overriding a built-in palette keeps its place
+        assert_eq!(theme.cycle(false).name, "high-contrast");
+        theme.select("Amber").unwrap();
+        assert_eq!(theme.palette().colors[1], 0xFFB000);
+
+        assert_eq!(
+            theme.apply("palette = \"neon\"").unwrap_err().to_string(),
+            "unknown palette `neon`, expected one of classic, amber, white, lcd, high-contrast, mine"
+        );
+        assert_eq!(
+            theme
+                .apply("[palettes]\nbad = \"#000000\"")
+                .unwrap_err()
+                .to_string(),
+            "palette `bad` must be a list of colors"
+        );
+    }
+}
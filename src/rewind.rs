@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+
+// Keeps the newest snapshot in full and every older one as a delta against the
+// snapshot that followed it, so rewinding walks the deltas from the back and
+// the oldest history can be dropped from the front without touching the rest.
+pub struct Rewind {
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    capacity: usize,
+    budget: usize,
+    used: usize,
+}
+
+impl Rewind {
+    // `capacity` is the number of frames to keep, `budget` the memory limit in bytes
+    pub fn new(capacity: usize, budget: usize) -> Self {
+        Self {
+            latest: None,
+            deltas: VecDeque::new(),
+            capacity,
+            budget,
+            used: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.used + self.latest.as_ref().map_or(0, Vec::len)
+    }
+
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            let delta = encode_delta(&snapshot, &previous);
+            self.used += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(snapshot);
+        while !self.deltas.is_empty()
+            && (self.deltas.len() > self.capacity || self.memory_used() > self.budget)
+        {
+            if let Some(delta) = self.deltas.pop_front() {
+                self.used -= delta.len();
+            }
+        }
+    }
+
+    // Returns the snapshot before the newest one, which then becomes the newest
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        self.used -= delta.len();
+        let latest = self.latest.as_ref()?;
+        let previous = apply_delta(latest, &delta);
+        self.latest = Some(previous.clone());
+        Some(previous)
+    }
+}
+
+// A delta is the target length followed by runs of unchanged bytes and XORed
+// literals, all lengths as LEB128. Bytes past the end of `from` count as zero.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = to
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| byte ^ from.get(i).copied().unwrap_or(0))
+        .collect();
+    let mut delta = Vec::new();
+    put_varint(&mut delta, to.len());
+    let mut pos = 0;
+    while pos < xor.len() {
+        let skip = xor[pos..].iter().take_while(|&&b| b == 0).count();
+        pos += skip;
+        if pos == xor.len() {
+            break;
+        }
+        let literal = xor[pos..].iter().take_while(|&&b| b != 0).count();
+        put_varint(&mut delta, skip);
+        put_varint(&mut delta, literal);
+        delta.extend_from_slice(&xor[pos..pos + literal]);
+        pos += literal;
+    }
+    delta
+}
+
+fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut input = delta;
+    let len = get_varint(&mut input);
+    let mut to: Vec<u8> = (0..len)
+        .map(|i| from.get(i).copied().unwrap_or(0))
+        .collect();
+    let mut pos = 0;
+    while !input.is_empty() {
+        pos += get_varint(&mut input);
+        let literal = get_varint(&mut input);
+        for (byte, &xor) in to[pos..pos + literal].iter_mut().zip(&input[..literal]) {
+            *byte ^= xor;
+        }
+        input = &input[literal..];
+        pos += literal;
+    }
+    to
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn get_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some((&byte, rest)) = input.split_first() {
+        *input = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let from = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        let to = vec![1, 2, 9, 4, 5, 6, 0, 8];
+        let delta = encode_delta(&from, &to);
+        assert!(delta.len() < to.len());
+        assert_eq!(apply_delta(&from, &delta), to);
+
+        let longer: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        assert_eq!(apply_delta(&from, &encode_delta(&from, &longer)), longer);
+        assert_eq!(apply_delta(&longer, &encode_delta(&longer, &from)), from);
+    }
+
+    #[test]
+    fn test_push_and_pop() {
+        let mut rewind = Rewind::new(10, 1024);
+        assert_eq!(rewind.pop(), None);
+        for frame in 0..4u8 {
+            rewind.push(vec![frame; 16]);
+        }
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.pop(), Some(vec![2; 16]));
+        assert_eq!(rewind.pop(), Some(vec![1; 16]));
+        rewind.push(vec![7; 16]);
+        assert_eq!(rewind.pop(), Some(vec![1; 16]));
+        assert_eq!(rewind.pop(), Some(vec![0; 16]));
+        assert_eq!(rewind.pop(), None);
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn test_capacity_and_budget() {
+        let mut rewind = Rewind::new(2, 1024);
+        for frame in 0..5u8 {
+            rewind.push(vec![frame; 16]);
+        }
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.pop(), Some(vec![3; 16]));
+        assert_eq!(rewind.pop(), Some(vec![2; 16]));
+        assert_eq!(rewind.pop(), None);
+
+        let mut rewind = Rewind::new(100, 64);
+        for frame in 0..10u8 {
+            rewind.push(vec![frame; 16]);
+        }
+        assert!(rewind.memory_used() <= 64);
+        assert!(rewind.len() < 9);
+    }
+}
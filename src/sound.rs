@@ -0,0 +1,82 @@
+use crate::beeper::{Beeper, SAMPLE_RATE, encode_wav};
+use ggez::{
+    Context,
+    audio::{SoundData, SoundSource, Source},
+};
+
+pub struct Sound {
+    beeper: Beeper,
+    source: Option<Source>,
+    pattern: Option<([u8; 16], u8)>,
+    muted: bool,
+}
+
+impl Sound {
+    pub fn new(ctx: &Context, beeper: Beeper, muted: bool) -> Self {
+        let mut sound = Self {
+            beeper,
+            source: None,
+            pattern: None,
+            muted,
+        };
+        sound.rebuild(ctx);
+        sound
+    }
+
+    // Playback is optional: a missing audio device should not stop the emulator
+    fn rebuild(&mut self, ctx: &Context) {
+        if let Some(source) = &mut self.source {
+            let _ = source.stop(ctx);
+        }
+        let samples = match &self.pattern {
+            Some((pattern, pitch)) => self.beeper.render_pattern(pattern, *pitch),
+            None => self.beeper.render_loop(),
+        };
+        let wav = encode_wav(&samples, SAMPLE_RATE);
+        self.source = match Source::from_data(ctx, SoundData::from_bytes(&wav)) {
+            Ok(mut source) => {
+                source.set_repeat(true);
+                match source.play_later() {
+                    Ok(()) => {
+                        source.pause();
+                        Some(source)
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to start audio: {}", e);
+                        None
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to create audio source: {}", e);
+                None
+            }
+        };
+    }
+
+    // `pattern` is the XO-CHIP audio pattern and pitch, if the program loaded one
+    pub fn update(&mut self, ctx: &Context, sound_timer: u8, pattern: Option<([u8; 16], u8)>) {
+        if pattern != self.pattern {
+            self.pattern = pattern;
+            self.rebuild(ctx);
+        }
+        let Some(source) = &self.source else {
+            return;
+        };
+        let beeping = sound_timer > 0 && !self.muted;
+        if beeping && source.paused() {
+            source.resume();
+        } else if !beeping && !source.paused() {
+            source.pause();
+        }
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+    }
+
+    pub fn change_volume(&mut self, ctx: &Context, delta: f32) {
+        self.beeper.set_volume(self.beeper.volume + delta);
+        self.rebuild(ctx);
+    }
+}
@@ -1,94 +1,740 @@
+use crate::beeper::Beeper;
+use crate::debugger::{self, Debugger};
 use crate::freq_timer::FrequencyTimer;
-use core::Chip8;
+use crate::gamepad::{Change, Gamepads};
+use crate::gdb::GdbStub;
+use crate::headless;
+use crate::keymap::{self, KEYPAD, Keymap, Rebinding};
+use crate::layout::{self, Scaling};
+use crate::movie::MovieMode;
+use crate::palette::Theme;
+use crate::rewind::Rewind;
+use crate::sound::Sound;
+use core::{Chip8, CpuFault, Platform, Quirks, SplitMix64, StepOutcome, Tracer, Watchpoint};
 use ggez::{
-    event::EventHandler,
-    graphics::{Color, DrawMode, Mesh},
-    input::keyboard::KeyCode,
+    conf::FullscreenType,
+    event::{Axis, Button, EventHandler, GamepadId},
+    graphics::{Color, DrawMode, Mesh, Text},
+    input::keyboard::{KeyCode, KeyInput, KeyMods},
     *,
 };
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-pub const SIZE: usize = 14;
-pub const SPACE: usize = 2;
-// Key mapping
-// 1 2 3 C -> 1 2 3 4
-// 4 5 6 D -> Q W E R
-// 7 8 9 E -> A S D F
-// A 0 B F -> Z X C V
-const KEYCODES: [KeyCode; 16] = [
-    KeyCode::X,
-    KeyCode::Key1,
-    KeyCode::Key2,
-    KeyCode::Key3,
-    KeyCode::Q,
-    KeyCode::W,
-    KeyCode::E,
-    KeyCode::A,
-    KeyCode::S,
-    KeyCode::D,
-    KeyCode::Z,
-    KeyCode::C,
-    KeyCode::Key4,
-    KeyCode::R,
-    KeyCode::F,
-    KeyCode::V,
+// Width of the debugger side panel
+pub const PANEL_WIDTH: f32 = 320.0;
+const PANEL_TEXT_SCALE: f32 = 16.0;
+const PANEL_LINE_HEIGHT: f32 = 18.0;
+// Spacing of the keypad on the rebinding screen
+const REBIND_CELL: (f32, f32) = (200.0, 100.0);
+const LISTING_BEFORE: usize = 6;
+const LISTING_AFTER: usize = 11;
+const IPS_STEP: u32 = 100;
+const VOLUME_STEP: f32 = 0.05;
+const STATUS_DURATION: Duration = Duration::from_secs(2);
+const REWIND_KEY: KeyCode = KeyCode::Back;
+const REBIND_KEY: KeyCode = KeyCode::F12;
+// Toggles fullscreen, as does Alt+Enter
+const FULLSCREEN_KEY: KeyCode = KeyCode::F11;
+// Cycles the palette, backwards with Shift
+const PALETTE_KEY: KeyCode = KeyCode::Tab;
+// Hotkeys that can't be bound, so a CHIP-8 key never also triggers one
+const RESERVED_KEYS: [KeyCode; 6] = [
+    PALETTE_KEY,
+    KeyCode::Equals,
+    KeyCode::Minus,
+    KeyCode::M,
+    KeyCode::LBracket,
+    KeyCode::RBracket,
 ];
+const SLOT_KEYS: [KeyCode; 10] = [
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+];
+// Debugger keys, active with `--debug`
+// P pause/resume, I step, O step over, U step out,
+// Up/Down move the cursor, B toggle a breakpoint at it, G run to it
+const DEBUGGER_KEYS: [KeyCode; 8] = [
+    KeyCode::P,
+    KeyCode::I,
+    KeyCode::O,
+    KeyCode::U,
+    KeyCode::Up,
+    KeyCode::Down,
+    KeyCode::B,
+    KeyCode::G,
+];
+
+// Hotkeys a CHIP-8 key can't share, including the debugger's when it is open
+pub fn reserved_keys(debug: bool) -> Vec<KeyCode> {
+    let mut keys = RESERVED_KEYS.to_vec();
+    if debug {
+        keys.extend(DEBUGGER_KEYS);
+    }
+    keys
+}
+
+pub struct Options {
+    pub rom_path: PathBuf,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub seed: Option<u64>,
+    pub ips: u32,
+    pub beeper: Beeper,
+    pub muted: bool,
+    pub rewind: Rewind,
+    pub debug: bool,
+    pub watchpoints: Vec<Watchpoint>,
+    pub gdb: Option<GdbStub>,
+    pub tracer: Option<Tracer>,
+    pub movie: Option<MovieMode>,
+    pub keymap: Keymap,
+    // Where rebinding saves the key map
+    pub keymap_path: Option<PathBuf>,
+    pub theme: Theme,
+    pub scaling: Scaling,
+    pub fullscreen: bool,
+}
+
+// A 60 Hz frame that a breakpoint interrupted, to be finished on resuming
+#[derive(Clone, Copy)]
+struct Frame {
+    keys: u16,
+    steps: usize,
+}
 
 pub struct State {
     chip8: Chip8,
+    rom_path: PathBuf,
     is_first_frame: bool,
-    gray: Color,
+    theme: Theme,
+    scaling: Scaling,
+    fullscreen: bool,
     timer_freq: FrequencyTimer,
+    cpu_freq: FrequencyTimer,
+    ips: u32,
+    sound: Sound,
+    fault: Option<CpuFault>,
+    status: Option<(String, Instant)>,
+    rewind: Rewind,
+    rewinding: bool,
+    debugger: Debugger,
+    gdb: Option<GdbStub>,
+    movie: Option<MovieMode>,
+    frame: Option<Frame>,
+    // Frames completed, less any undone by rewinding
+    frame_count: usize,
+    keymap: Keymap,
+    keymap_path: Option<PathBuf>,
+    rebinding: Option<Rebinding>,
+    gamepads: Gamepads<GamepadId>,
 }
 
 impl State {
-    pub fn new(rom: &[u8]) -> Self {
+    pub fn new(ctx: &Context, rom: &[u8], options: Options) -> Self {
+        let mut chip8 = Chip8::new(rom, options.platform, options.quirks);
+        if let Some(seed) = options.seed {
+            chip8.set_random_source(SplitMix64::new(seed));
+        }
+        chip8.set_tracer(options.tracer);
+        for watchpoint in options.watchpoints {
+            chip8.add_watchpoint(watchpoint);
+        }
         Self {
-            chip8: Chip8::new(rom),
+            chip8,
+            rom_path: options.rom_path,
             is_first_frame: true,
-            gray: Color::from_rgb_u32(0x101010),
+            theme: options.theme,
+            scaling: options.scaling,
+            fullscreen: options.fullscreen,
             timer_freq: FrequencyTimer::new(60),
+            cpu_freq: FrequencyTimer::new(options.ips),
+            ips: options.ips,
+            sound: Sound::new(ctx, options.beeper, options.muted),
+            fault: None,
+            status: None,
+            rewind: options.rewind,
+            rewinding: false,
+            debugger: Debugger::new(options.debug),
+            gdb: options.gdb,
+            movie: options.movie,
+            frame: None,
+            frame_count: 0,
+            keymap: options.keymap,
+            keymap_path: options.keymap_path,
+            rebinding: None,
+            gamepads: Gamepads::new(),
+        }
+    }
+
+    fn set_status(&mut self, message: String) {
+        self.status = Some((message, Instant::now()));
+    }
+
+    fn save_slot(&mut self, slot: usize) {
+        let path = slot_path(&self.rom_path, slot);
+        match fs::write(&path, self.chip8.save_state()) {
+            Ok(()) => self.set_status(format!("Saved slot {}", slot)),
+            Err(e) => self.set_status(format!("Failed to save {}: {}", path.display(), e)),
+        }
+    }
+
+    fn load_slot(&mut self, slot: usize) {
+        if self.movie.is_some() {
+            self.set_status("Save states cannot be loaded during a movie".to_string());
+            return;
+        }
+        let path = slot_path(&self.rom_path, slot);
+        let result = fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|state| Ok(self.chip8.load_state(&state)?));
+        match result {
+            Ok(()) => {
+                self.fault = None;
+                self.frame = None;
+                self.set_status(format!("Loaded slot {}", slot));
+            }
+            Err(e) => self.set_status(format!("Failed to load {}: {}", path.display(), e)),
+        }
+    }
+
+    // Restores one snapshot per frame, so history plays backwards at normal speed
+    fn step_back(&mut self, ctx: &Context) {
+        let elapsed_ms = ctx.time.delta().as_secs_f32() * 1000.0;
+        for _ in 0..self.timer_freq.update(elapsed_ms) {
+            let Some(snapshot) = self.rewind.pop() else {
+                break;
+            };
+            match self.chip8.load_state(&snapshot) {
+                Ok(()) => {
+                    self.fault = None;
+                    self.frame = None;
+                    self.frame_count = self.frame_count.saturating_sub(1);
+                }
+                Err(e) => eprintln!("Failed to rewind: {}", e),
+            }
+        }
+        self.sound.update(ctx, 0, None);
+    }
+
+    // The keys for the next frame, from the movie when one is playing
+    fn next_keys(&mut self, live: u16) -> u16 {
+        let Some(movie) = &mut self.movie else {
+            return live;
+        };
+        match movie.keys(self.frame_count, live) {
+            Some(keys) => keys,
+            None => {
+                self.movie = None;
+                self.set_status(format!("Playback ended after {} frames", self.frame_count));
+                live
+            }
+        }
+    }
+
+    // Runs what is left of the current frame, then its vblank. Returns false
+    // when a breakpoint, exit, fault or watchpoint interrupts it.
+    fn finish_frame(&mut self, ctx: &mut Context) -> bool {
+        let Some(frame) = self.frame else {
+            return true;
+        };
+        for steps in (0..frame.steps).rev() {
+            if self.debugger.should_break(&self.chip8.cpu) {
+                return false;
+            }
+            self.frame = Some(Frame { steps, ..frame });
+            if !self.execute(ctx, frame.keys) {
+                return false;
+            }
+        }
+        self.frame = None;
+        self.frame_count += 1;
+        headless::end_frame(&mut self.chip8);
+        self.rewind.push(self.chip8.save_state());
+        true
+    }
+
+    // Returns false when the program exits, faults or hits a watchpoint
+    fn execute(&mut self, ctx: &mut Context, key: u16) -> bool {
+        match self.chip8.step(key) {
+            Ok(StepOutcome::Exited) => {
+                ctx.request_quit();
+                false
+            }
+            Ok(StepOutcome::Stopped(reason)) => {
+                self.debugger.pause(self.chip8.cpu.get_pc());
+                self.set_status(reason.to_string());
+                false
+            }
+            Ok(_) => true,
+            Err(fault) => {
+                eprintln!("{}", fault);
+                self.fault = Some(fault);
+                if self.debugger.is_enabled() {
+                    self.debugger.pause(self.chip8.cpu.get_pc());
+                }
+                false
+            }
+        }
+    }
+
+    fn debugger_key(&mut self, keycode: KeyCode) {
+        let cpu = &self.chip8.cpu;
+        match keycode {
+            KeyCode::P if self.debugger.is_paused() => self.debugger.resume(),
+            KeyCode::P => self.debugger.pause(cpu.get_pc()),
+            KeyCode::I => self.debugger.step(),
+            KeyCode::O => self.debugger.step_over(cpu, self.chip8.get_memory()),
+            KeyCode::U if cpu.get_sp() == 0 => self.set_status("Not in a subroutine".to_string()),
+            KeyCode::U => self.debugger.step_out(cpu),
+            KeyCode::G => self.debugger.run_to_cursor(),
+            KeyCode::B => self.debugger.toggle_breakpoint(self.debugger.cursor()),
+            KeyCode::Up => self.debugger.move_cursor(-2),
+            KeyCode::Down => self.debugger.move_cursor(2),
+            _ => {}
+        }
+    }
+
+    fn rebind_key(&mut self, keycode: KeyCode) {
+        let Some(rebinding) = &mut self.rebinding else {
+            return;
+        };
+        if keycode == KeyCode::Escape {
+            if let Some(rebinding) = self.rebinding.take() {
+                self.keymap = rebinding.cancel();
+            }
+            self.set_status("Rebinding cancelled".to_string());
+            return;
+        }
+        let reserved = reserved_keys(self.debugger.is_enabled()).contains(&keycode);
+        if reserved || keymap::key_name(keycode).is_none() {
+            self.set_status(format!("{:?} cannot be bound", keycode));
+            return;
+        }
+        if rebinding.capture(&mut self.keymap, keycode) {
+            self.rebinding = None;
+            self.save_keymap();
+        }
+    }
+
+    fn save_keymap(&mut self) {
+        let Some(path) = self.keymap_path.clone().or_else(keymap::global_path) else {
+            self.set_status("No config directory to save the key map in".to_string());
+            return;
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&path, self.keymap.to_toml()));
+        match result {
+            Ok(()) => self.set_status(format!("Saved key map to {}", path.display())),
+            Err(e) => self.set_status(format!("Failed to save {}: {}", path.display(), e)),
+        }
+    }
+
+    // The hex keypad with each key's bindings, highlighting the one being captured
+    fn draw_rebinding(
+        &self,
+        ctx: &Context,
+        canvas: &mut graphics::Canvas,
+        rebinding: &Rebinding,
+    ) -> GameResult {
+        let (width, height) = ctx.gfx.drawable_size();
+        let backdrop = Mesh::new_rectangle(
+            ctx,
+            DrawMode::fill(),
+            graphics::Rect::new(0.0, 0.0, width, height),
+            Color::new(0.0, 0.0, 0.0, 0.85),
+        )?;
+        canvas.draw(&backdrop, graphics::DrawParam::default());
+        let mut title = Text::new(format!(
+            "Press a key for {:X}, Esc to cancel",
+            rebinding.key()
+        ));
+        title.set_scale(20.0);
+        canvas.draw(
+            &title,
+            graphics::DrawParam::default()
+                .dest([8.0, 8.0])
+                .color(Color::YELLOW),
+        );
+        for (position, &key) in KEYPAD.iter().enumerate() {
+            let names: Vec<&str> = self
+                .keymap
+                .bindings(key)
+                .iter()
+                .filter_map(|&k| keymap::key_name(k))
+                .collect();
+            let mut text = Text::new(format!("{:X}\n{}", key, names.join(" ")));
+            text.set_scale(PANEL_TEXT_SCALE);
+            let color = if key == rebinding.key() {
+                Color::CYAN
+            } else {
+                Color::WHITE
+            };
+            let (column, row) = (position % 4, position / 4);
+            canvas.draw(
+                &text,
+                graphics::DrawParam::default()
+                    .dest([
+                        24.0 + column as f32 * REBIND_CELL.0,
+                        48.0 + row as f32 * REBIND_CELL.1,
+                    ])
+                    .color(color),
+            );
+        }
+        Ok(())
+    }
+
+    fn draw_panel(&self, canvas: &mut graphics::Canvas, x: f32) {
+        let cpu = &self.chip8.cpu;
+        let state = if self.debugger.is_paused() {
+            ("Paused".to_string(), Color::YELLOW)
+        } else if let Some(text) = debugger::key_wait_text(cpu) {
+            (text, Color::CYAN)
+        } else {
+            ("Running".to_string(), Color::GREEN)
+        };
+        let mut lines = vec![state];
+        lines.extend(
+            debugger::registers_text(cpu)
+                .into_iter()
+                .map(|line| (line, Color::WHITE)),
+        );
+        lines.push((String::new(), Color::WHITE));
+        // The listing follows the PC while running and the cursor while paused
+        let center = if self.debugger.is_paused() {
+            self.debugger.cursor()
+        } else {
+            cpu.get_pc()
+        };
+        let rows = debugger::listing(
+            self.chip8.get_memory(),
+            center,
+            LISTING_BEFORE,
+            LISTING_AFTER,
+        );
+        for (addr, text) in rows {
+            let marker = if addr == cpu.get_pc() { '>' } else { ' ' };
+            let breakpoint = if self.debugger.has_breakpoint(addr) {
+                '*'
+            } else {
+                ' '
+            };
+            let color = if self.debugger.is_paused() && addr == self.debugger.cursor() {
+                Color::CYAN
+            } else {
+                Color::WHITE
+            };
+            lines.push((format!("{}{} {}", marker, breakpoint, text), color));
+        }
+        for (row, (line, color)) in lines.into_iter().enumerate() {
+            let mut text = Text::new(line);
+            text.set_scale(PANEL_TEXT_SCALE);
+            canvas.draw(
+                &text,
+                graphics::DrawParam::default()
+                    .dest([x + 8.0, 8.0 + row as f32 * PANEL_LINE_HEIGHT])
+                    .color(color),
+            );
         }
     }
+
+    // Announces pads as they come and go; an unplugged pad's keys are released
+    fn sync_gamepads(&mut self, ctx: &Context) {
+        let connected: Vec<GamepadId> = ctx.gamepad.gamepads().map(|(id, _)| id).collect();
+        for change in self.gamepads.sync(&connected) {
+            let message = match change {
+                Change::Connected(id) => {
+                    format!("Gamepad connected: {}", ctx.gamepad.gamepad(id).name())
+                }
+                Change::Disconnected(_) => "Gamepad disconnected".to_string(),
+            };
+            self.set_status(message);
+        }
+    }
+
+    fn toggle_fullscreen(&mut self, ctx: &mut Context) {
+        let fullscreen = if self.fullscreen {
+            FullscreenType::Windowed
+        } else {
+            FullscreenType::Desktop
+        };
+        match ctx.gfx.set_fullscreen(fullscreen) {
+            Ok(()) => self.fullscreen = !self.fullscreen,
+            Err(e) => self.set_status(format!("Failed to change fullscreen: {}", e)),
+        }
+    }
+
+    fn set_ips(&mut self, ctx: &Context, ips: u32) {
+        if self.movie.is_some() {
+            self.set_status("IPS cannot change during a movie".to_string());
+            return;
+        }
+        self.ips = ips;
+        self.cpu_freq.set_frequency(ips);
+        ctx.gfx.set_window_title(&title(ips));
+    }
+}
+
+pub fn title(ips: u32) -> String {
+    format!("CHIP-8 Emulator - {} IPS", ips)
+}
+
+// Save slots live next to the ROM, e.g. `game.ch8` -> `game.state1`
+fn slot_path(rom_path: &Path, slot: usize) -> PathBuf {
+    rom_path.with_extension(format!("state{}", slot))
 }
 
 impl EventHandler for State {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let key = KEYCODES.iter().enumerate().fold(0, |acc, (i, &kc)| {
-            let pressed = ctx.keyboard.is_key_pressed(kc);
-            acc | if pressed { 1 << i } else { 0 }
-        });
+        if let Some(gdb) = &mut self.gdb {
+            gdb.poll(&mut self.chip8, &mut self.debugger);
+        }
+        self.sync_gamepads(ctx);
+        self.rewinding = ctx.keyboard.is_key_pressed(REWIND_KEY);
+        if self.rewinding {
+            self.step_back(ctx);
+            return Ok(());
+        }
+        if self.fault.is_some() {
+            self.sound.update(ctx, 0, None);
+            return Ok(());
+        }
+        if self.debugger.is_paused() || self.rebinding.is_some() {
+            self.sound.update(ctx, 0, None);
+            return Ok(());
+        }
+        let key = self.keymap.mask(|k| ctx.keyboard.is_key_pressed(k))
+            | self.gamepads.mask(&self.keymap.gamepad);
 
-        self.chip8.step(key);
         if !self.is_first_frame {
+            // Frames run on the same virtual clock as headless mode, so movies replay exactly
             let elapsed_ms = ctx.time.delta().as_secs_f32() * 1000.0;
-            for _ in 0..self.timer_freq.update(elapsed_ms) {
-                self.chip8.dec_delay_timer();
-                self.chip8.dec_sound_timer();
+            let frames = self.timer_freq.update(elapsed_ms);
+            if !self.finish_frame(ctx) {
+                return Ok(());
             }
+            for _ in 0..frames {
+                let keys = self.next_keys(key);
+                let steps = headless::frame_steps(&mut self.cpu_freq);
+                self.frame = Some(Frame { keys, steps });
+                if !self.finish_frame(ctx) {
+                    return Ok(());
+                }
+            }
+            let pattern = self
+                .chip8
+                .get_audio_pattern()
+                .map(|pattern| (*pattern, self.chip8.get_pitch()));
+            self.sound
+                .update(ctx, self.chip8.get_sound_timer(), pattern);
         } else {
             self.is_first_frame = false;
         }
         Ok(())
     }
 
+    fn key_down_event(
+        &mut self,
+        ctx: &mut Context,
+        input: KeyInput,
+        _repeated: bool,
+    ) -> GameResult {
+        if self.rebinding.is_some() {
+            if let Some(keycode) = input.keycode {
+                self.rebind_key(keycode);
+            }
+            return Ok(());
+        }
+        match input.keycode {
+            Some(KeyCode::Escape) => ctx.request_quit(),
+            Some(FULLSCREEN_KEY) => self.toggle_fullscreen(ctx),
+            Some(KeyCode::Return) if input.mods.contains(KeyMods::ALT) => {
+                self.toggle_fullscreen(ctx)
+            }
+            Some(REBIND_KEY) => self.rebinding = Some(Rebinding::new(&self.keymap)),
+            Some(KeyCode::Equals) => self.set_ips(ctx, self.ips + IPS_STEP),
+            Some(KeyCode::Minus) if self.ips > IPS_STEP => self.set_ips(ctx, self.ips - IPS_STEP),
+            Some(KeyCode::M) => self.sound.toggle_mute(),
+            Some(PALETTE_KEY) => {
+                let name = self
+                    .theme
+                    .cycle(!input.mods.contains(KeyMods::SHIFT))
+                    .name
+                    .clone();
+                self.set_status(format!("Palette: {}", name));
+            }
+            Some(KeyCode::RBracket) => self.sound.change_volume(ctx, VOLUME_STEP),
+            Some(KeyCode::LBracket) => self.sound.change_volume(ctx, -VOLUME_STEP),
+            Some(keycode) if self.debugger.is_enabled() && DEBUGGER_KEYS.contains(&keycode) => {
+                self.debugger_key(keycode)
+            }
+            Some(keycode) => {
+                if let Some(index) = SLOT_KEYS.iter().position(|&k| k == keycode) {
+                    if input.mods.contains(KeyMods::SHIFT) {
+                        self.save_slot(index + 1);
+                    } else {
+                        self.load_slot(index + 1);
+                    }
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn gamepad_button_down_event(
+        &mut self,
+        _ctx: &mut Context,
+        button: Button,
+        id: GamepadId,
+    ) -> GameResult {
+        self.gamepads.button(id, button, true);
+        Ok(())
+    }
+
+    fn gamepad_button_up_event(
+        &mut self,
+        _ctx: &mut Context,
+        button: Button,
+        id: GamepadId,
+    ) -> GameResult {
+        self.gamepads.button(id, button, false);
+        Ok(())
+    }
+
+    fn gamepad_axis_event(
+        &mut self,
+        _ctx: &mut Context,
+        axis: Axis,
+        value: f32,
+        id: GamepadId,
+    ) -> GameResult {
+        self.gamepads.axis(id, axis, value);
+        Ok(())
+    }
+
+    fn quit_event(&mut self, _ctx: &mut Context) -> GameResult<bool> {
+        if let Some(tracer) = self.chip8.set_tracer(None)
+            && let Err(e) = tracer.finish()
+        {
+            eprintln!("Failed to write trace: {}", e);
+        }
+        if let Some(MovieMode::Recording { movie, path }) = &self.movie {
+            match movie.save(path) {
+                Ok(()) => println!(
+                    "Recorded {} frames to {}",
+                    movie.frames.len(),
+                    path.display()
+                ),
+                Err(e) => eprintln!("Failed to save movie {}: {}", path.display(), e),
+            }
+        }
+        Ok(false)
+    }
+
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         let mut canvas = graphics::Canvas::from_frame(ctx, graphics::Color::BLACK);
-        let mut mb = graphics::MeshBuilder::new();
         let display = self.chip8.get_display();
-        for (y, row) in display.iter().enumerate() {
-            for (x, &pixel) in row.iter().enumerate() {
-                let rect = graphics::Rect::new(
-                    (x * (SIZE + SPACE)) as f32,
-                    (y * (SIZE + SPACE)) as f32,
-                    SIZE as f32,
-                    SIZE as f32,
-                );
-                let color = if pixel { Color::GREEN } else { self.gray };
-                mb.rectangle(DrawMode::fill(), rect, color)?;
-            }
-        }
-        let mesh = Mesh::from_data(ctx, mb.build());
-        canvas.draw(&mesh, graphics::DrawParam::default());
+        let (mut width, height) = ctx.gfx.drawable_size();
+        if self.debugger.is_enabled() {
+            width = (width - PANEL_WIDTH).max(0.0);
+            self.draw_panel(&mut canvas, width);
+        }
+        let layout = layout::layout(
+            (width, height),
+            display[0].len(),
+            display.len(),
+            self.scaling,
+            self.theme.gap,
+        );
+        let palette = self.theme.palette().colors.map(Color::from_rgb_u32);
+        // Nothing to draw while the window is minimized
+        if layout.size > 0.0 {
+            let mut mb = graphics::MeshBuilder::new();
+            for (y, row) in display.iter().enumerate() {
+                for (x, &pixel) in row.iter().enumerate() {
+                    let (left, top) = layout.pixel(x, y);
+                    let rect = graphics::Rect::new(left, top, layout.size, layout.size);
+                    let color = palette[pixel as usize & 0b11];
+                    mb.rectangle(DrawMode::fill(), rect, color)?;
+                }
+            }
+            let mesh = Mesh::from_data(ctx, mb.build());
+            canvas.draw(&mesh, graphics::DrawParam::default());
+        }
+        if let Some(fault) = &self.fault {
+            let mut text = Text::new(format!("Halted: {}", fault));
+            text.set_scale(24.0);
+            canvas.draw(
+                &text,
+                graphics::DrawParam::default()
+                    .dest([8.0, 8.0])
+                    .color(Color::RED),
+            );
+        }
+        if let Some(rebinding) = &self.rebinding {
+            self.draw_rebinding(ctx, &mut canvas, rebinding)?;
+        } else if self.rewinding {
+            let label = if self.rewind.is_empty() {
+                "<< Rewind: start of history".to_string()
+            } else {
+                format!("<< Rewind: {:.1}s", self.rewind.len() as f32 / 60.0)
+            };
+            let mut text = Text::new(label);
+            text.set_scale(20.0);
+            canvas.draw(
+                &text,
+                graphics::DrawParam::default()
+                    .dest([8.0, 8.0])
+                    .color(Color::YELLOW),
+            );
+        }
+        // Status messages take the place of the FX0A status while they last
+        let status = match &self.status {
+            Some((message, shown_at)) if shown_at.elapsed() < STATUS_DURATION => {
+                Some((message.clone(), Color::YELLOW))
+            }
+            _ => debugger::key_wait_text(&self.chip8.cpu).map(|text| (text, Color::CYAN)),
+        };
+        if let Some((message, color)) = status {
+            let mut text = Text::new(message);
+            text.set_scale(20.0);
+            let (_, height) = ctx.gfx.drawable_size();
+            canvas.draw(
+                &text,
+                graphics::DrawParam::default()
+                    .dest([8.0, height - 28.0])
+                    .color(color),
+            );
+        }
         canvas.finish(ctx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_path() {
+        assert_eq!(
+            slot_path(Path::new("roms/game.ch8"), 3),
+            PathBuf::from("roms/game.state3")
+        );
+        assert_eq!(
+            slot_path(Path::new("game"), 10),
+            PathBuf::from("game.state10")
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use crate::debugger::instruction_at;
+use crate::freq_timer::FrequencyTimer;
+use crate::headless::{self, Script};
+use core::{Chip8, Platform, Quirks, Tracer};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Divergence {
+    // Position among the traced instructions, from 0
+    pub index: usize,
+    // The fields that differ, e.g. `PC` or `V3`, or which trace ended first
+    pub fields: Vec<String>,
+}
+
+// The fields of a trace line, without the cycle count and mnemonic, which
+// legitimately differ between emulators
+fn state(line: &str) -> Vec<(String, &str)> {
+    let state = line.split(" ; ").next().unwrap_or_default();
+    let mut fields = Vec::new();
+    for field in state.split_whitespace().skip(1) {
+        let (name, value) = field.split_once('=').unwrap_or((field, ""));
+        if name == "V" {
+            for (x, pair) in value.as_bytes().chunks(2).enumerate() {
+                let value = std::str::from_utf8(pair).unwrap_or_default();
+                fields.push((format!("V{:X}", x), value));
+            }
+        } else {
+            fields.push((name.to_string(), value));
+        }
+    }
+    fields
+}
+
+// Lines are aligned by position, since emulators count waiting cycles differently
+pub fn compare(a: &[&str], b: &[&str]) -> Option<Divergence> {
+    for index in 0..a.len().max(b.len()) {
+        let fields = match (a.get(index), b.get(index)) {
+            (Some(a), Some(b)) => {
+                let (a, b) = (state(a), state(b));
+                let mut fields: Vec<String> = a
+                    .iter()
+                    .zip(b.iter())
+                    .filter(|(a, b)| a != b)
+                    .map(|((name, _), _)| name.clone())
+                    .collect();
+                if a.len() != b.len() {
+                    fields.push("format".to_string());
+                }
+                fields
+            }
+            (None, _) => vec!["end of a".to_string()],
+            (_, None) => vec!["end of b".to_string()],
+        };
+        if !fields.is_empty() {
+            return Some(Divergence { index, fields });
+        }
+    }
+    None
+}
+
+// The matching lines before the divergence once, then both sides from it on
+pub fn report(a: &[&str], b: &[&str], divergence: &Divergence, context: usize) -> String {
+    let index = divergence.index;
+    let mut out = format!(
+        "First divergence at instruction {} ({})\n",
+        index + 1,
+        divergence.fields.join(", ")
+    );
+    for (i, line) in a
+        .iter()
+        .enumerate()
+        .take(index)
+        .skip(index.saturating_sub(context))
+    {
+        let _ = writeln!(out, "    {:>8}  {}", i + 1, line);
+    }
+    for i in index..=index + context {
+        for (side, lines) in [("a", a), ("b", b)] {
+            if let Some(line) = lines.get(i) {
+                let _ = writeln!(out, "{}   {:>8}  {}", side, i + 1, line);
+            }
+        }
+    }
+    out
+}
+
+// Keeps the last lines a tracer wrote
+#[derive(Clone)]
+struct Recent(Rc<RefCell<VecDeque<String>>>, usize);
+
+impl Write for Recent {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut lines = self.0.borrow_mut();
+        for line in String::from_utf8_lossy(buf).lines() {
+            if lines.len() == self.1 {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct Lockstep {
+    pub steps: u64,
+    pub pc: usize,
+    pub instruction: String,
+    pub fields: Vec<String>,
+    // The trace of each side around the divergence
+    pub context: [Vec<String>; 2],
+}
+
+pub enum Outcome {
+    // The runs matched for this many steps
+    Matched(u64),
+    Diverged(Box<Lockstep>),
+}
+
+pub struct LockstepConfig<'a> {
+    pub rom: &'a [u8],
+    pub platform: Platform,
+    pub quirks: [Quirks; 2],
+    pub seed: u64,
+    pub ips: u32,
+    pub frames: u32,
+    pub script: &'a Script,
+    pub context: usize,
+}
+
+// Runs both machines on the same input and stops at the first step after
+// which their registers, memory or screen differ
+pub fn lockstep(config: &LockstepConfig) -> Outcome {
+    let recent: [Recent; 2] =
+        std::array::from_fn(|_| Recent(Rc::default(), config.context * 2 + 1));
+    let mut machines: [Chip8; 2] = std::array::from_fn(|side| {
+        let mut chip8 = Chip8::new(config.rom, config.platform, config.quirks[side]);
+        chip8.set_random_source(core::SplitMix64::new(config.seed));
+        chip8.set_tracer(Some(Tracer::new(recent[side].clone(), Vec::new())));
+        chip8
+    });
+    let mut cpu_freq = FrequencyTimer::new(config.ips);
+    let mut steps = 0;
+    for frame in 0..config.frames {
+        let key = config.script.keys_at(frame);
+        for _ in 0..headless::frame_steps(&mut cpu_freq) {
+            let pc = machines[0].cpu.get_pc();
+            let results = machines.each_mut().map(|chip8| chip8.step(key));
+            steps += 1;
+            let mut fields = differences(&machines[0], &machines[1]);
+            if results[0] != results[1] {
+                fields.insert(0, "outcome".to_string());
+            }
+            if !fields.is_empty() {
+                for _ in 0..config.context {
+                    for chip8 in machines.iter_mut() {
+                        let _ = chip8.step(key);
+                    }
+                }
+                let instruction = instruction_at(machines[0].get_memory(), pc)
+                    .map_or_else(|| "???".to_string(), |i| i.to_string());
+                let context = recent.map(|recent| recent.0.borrow().iter().cloned().collect());
+                return Outcome::Diverged(Box::new(Lockstep {
+                    steps,
+                    pc,
+                    instruction,
+                    fields,
+                    context,
+                }));
+            }
+            if results[0].is_err() || results[0] == Ok(core::StepOutcome::Exited) {
+                return Outcome::Matched(steps);
+            }
+        }
+        for chip8 in machines.iter_mut() {
+            headless::end_frame(chip8);
+        }
+    }
+    Outcome::Matched(steps)
+}
+
+fn differences(a: &Chip8, b: &Chip8) -> Vec<String> {
+    let (ra, rb) = (a.registers(), b.registers());
+    let mut fields: Vec<String> = (0..16)
+        .filter(|&x| ra.v[x] != rb.v[x])
+        .map(|x| format!("V{:X}", x))
+        .collect();
+    let registers = [
+        ("I", ra.i != rb.i),
+        ("PC", ra.pc != rb.pc),
+        ("stack", ra.stack != rb.stack),
+        ("DT", ra.dt != rb.dt),
+        ("ST", ra.st != rb.st),
+    ];
+    fields.extend(
+        registers
+            .iter()
+            .filter(|&&(_, differs)| differs)
+            .map(|&(name, _)| name.to_string()),
+    );
+    // Memory only changes where a step writes, and the runs are compared after
+    // every step, so the ranges either side just wrote are all that can differ
+    let memory = [a, b]
+        .iter()
+        .filter_map(|chip8| chip8.cpu.get_last_write())
+        .filter_map(|(first, last)| {
+            let range = first..=last;
+            a.get_memory()[range.clone()]
+                .iter()
+                .zip(&b.get_memory()[range])
+                .position(|(a, b)| a != b)
+                .map(|offset| first + offset)
+        })
+        .min();
+    if let Some(addr) = memory {
+        fields.push(format!("memory at 0x{:03X}", addr));
+    }
+    if a.get_display() != b.get_display() {
+        fields.push("display".to_string());
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: [&str; 4] = [
+        "0000000001 PC=0200 OP=6005 V=05000000000000000000000000000000 I=0000 SP=00 DT=00 ST=00 W=--------- ; LD V0, 0x05",
+        "0000000002 PC=0202 OP=A300 V=05000000000000000000000000000000 I=0300 SP=00 DT=00 ST=00 W=--------- ; LD I, 0x300",
+        "0000000003 PC=0204 OP=F055 V=05000000000000000000000000000000 I=0301 SP=00 DT=00 ST=00 W=0300-0300 ; LD [I], V0",
+        "0000000004 PC=0206 OP=8016 V=02000000000000000000000000000001 I=0301 SP=00 DT=00 ST=00 W=--------- ; SHR V0, V1",
+    ];
+
+    #[test]
+    fn test_compare() {
+        let a = A.to_vec();
+        assert_eq!(compare(&a, &a), None);
+        // Another emulator with different cycle counts and mnemonics, and no I increment
+        let b: Vec<String> = A
+            .iter()
+            .map(|line| {
+                line.replacen("00000000", "00000100", 1)
+                    .replace("SHR", "shr")
+            })
+            .map(|line| line.replacen("I=0301", "I=0300", 1))
+            .collect();
+        let b: Vec<&str> = b.iter().map(String::as_str).collect();
+        assert_eq!(
+            compare(&a, &b),
+            Some(Divergence {
+                index: 2,
+                fields: vec!["I".to_string()]
+            })
+        );
+        assert_eq!(
+            compare(&a, &a[..3]),
+            Some(Divergence {
+                index: 3,
+                fields: vec!["end of b".to_string()]
+            })
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let a = A.to_vec();
+        let mut b = a.clone();
+        let changed = A[3].replace("V=02", "V=0A");
+        b[3] = &changed;
+        let divergence = compare(&a, &b).unwrap();
+        assert_eq!(divergence.fields, ["V0"]);
+        let report = report(&a, &b, &divergence, 1);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "First divergence at instruction 4 (V0)");
+        assert!(lines[1].starts_with("           3  0000000003"));
+        assert!(lines[2].starts_with("a          4  0000000004"));
+        assert!(lines[3].starts_with("b          4  0000000004"));
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn test_lockstep_finds_the_quirk() {
+        // LD V1, 3; LD V0, 0; SHR V0, V1; JP 0x206
+        let rom = [0x61, 0x03, 0x60, 0x00, 0x80, 0x16, 0x12, 0x06];
+        let script = Script::parse("").unwrap();
+        let mut config = LockstepConfig {
+            rom: &rom,
+            platform: Platform::Chip8,
+            quirks: [Quirks::VIP, Quirks::SUPER_CHIP],
+            seed: 0,
+            ips: 600,
+            frames: 10,
+            script: &script,
+            context: 1,
+        };
+        let Outcome::Diverged(divergence) = lockstep(&config) else {
+            panic!("the runs should diverge");
+        };
+        assert_eq!(divergence.steps, 3);
+        assert_eq!(divergence.pc, 0x204);
+        assert_eq!(divergence.instruction, "SHR V0, V1");
+        assert_eq!(divergence.fields, ["V0", "VF"]);
+        assert_eq!(divergence.context[0].len(), 3);
+        assert!(divergence.context[0][1].contains("PC=0204"));
+
+        config.quirks = [Quirks::VIP, Quirks::VIP];
+        assert!(matches!(lockstep(&config), Outcome::Matched(_)));
+    }
+}